@@ -1,8 +1,10 @@
 pub use acpi::*;
 
 use crate::println;
+use crate::vmem::{self, Attributes, Owner};
 use core::ptr::NonNull;
-use x86_64::VirtAddr;
+use x86_64::structures::paging::{Page, PageSize, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
 
 pub fn get_tables(rsdp: u64, physical_memory_offset: VirtAddr) -> AcpiTables<impl AcpiHandler> {
     let mapping = OffsetMapped(physical_memory_offset.as_u64());
@@ -14,6 +16,8 @@ pub fn get_tables(rsdp: u64, physical_memory_offset: VirtAddr) -> AcpiTables<imp
     tables
 }
 
+/// Maps tables read-only through the virtual memory manager, or through the
+/// physical memory mapping at the given offset if there is no manager yet.
 #[derive(Clone)]
 pub struct OffsetMapped(pub u64);
 
@@ -23,14 +27,47 @@ impl AcpiHandler for OffsetMapped {
         physical_address: usize,
         size: usize,
     ) -> PhysicalMapping<Self, T> {
-        PhysicalMapping::new(
-            physical_address,
-            NonNull::new((physical_address + self.0 as usize) as *mut _).unwrap(),
-            size,
-            size,
-            Self(self.0),
-        )
+        let phys = PhysAddr::new(physical_address as u64);
+        let first = phys.align_down(Size4KiB::SIZE);
+        let frames = (phys + size as u64 - first + Size4KiB::SIZE - 1) / Size4KiB::SIZE;
+
+        let pages = vmem::MANAGER.lock().get_mut().and_then(|manager| {
+            manager.allocate::<Size4KiB>(
+                first,
+                frames,
+                Attributes::new().writable(false),
+                Owner::Acpi,
+            )
+        });
+
+        match pages {
+            Some(pages) => PhysicalMapping::new(
+                physical_address,
+                NonNull::new((pages.start.start_address() + (phys - first)).as_mut_ptr()).unwrap(),
+                size,
+                (frames * Size4KiB::SIZE) as usize,
+                Self(self.0),
+            ),
+            None => PhysicalMapping::new(
+                physical_address,
+                NonNull::new((physical_address + self.0 as usize) as *mut _).unwrap(),
+                size,
+                size,
+                Self(self.0),
+            ),
+        }
     }
 
-    fn unmap_physical_region<T>(_region: &PhysicalMapping<Self, T>) {}
+    fn unmap_physical_region<T>(region: &PhysicalMapping<Self, T>) {
+        let virt = VirtAddr::from_ptr(region.virtual_start().as_ptr());
+        if virt.as_u64() == region.physical_start() as u64 + region.handler().0 {
+            return;
+        }
+
+        let first = Page::<Size4KiB>::containing_address(virt);
+        let frames = region.mapped_length() as u64 / Size4KiB::SIZE;
+        if let Some(manager) = vmem::MANAGER.lock().get_mut() {
+            manager.deallocate(Page::range(first, first + frames));
+        }
+    }
 }
@@ -1,48 +1,260 @@
+use crate::memory::{self, FRAMES};
+use crate::vmem::Attributes;
 use alloc::alloc::{GlobalAlloc, Layout};
+use core::ops::Range;
 use core::ptr::null_mut;
-use fixed_size_block::FixedSizeBlockAllocator;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::{
+    structures::idt::PageFaultErrorCode,
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page,
+        PageSize, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
 
 pub mod bump;
+pub mod conformance;
+pub mod debug;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod stats;
+pub mod tracking;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// The size the heap may grow to, which is reserved up front.
 pub const HEAP_SIZE: usize = 2 * 1024 * 1024 * 1024; // 2 GiB
+pub const HEAP_INITIAL_SIZE: usize = 1024 * 1024; // 1 MiB
 
+#[cfg(all(feature = "bump-allocator", feature = "linked-list-allocator"))]
+compile_error!("only one global allocator can be selected");
+
+/// The allocator of the kernel heap, selected through the features of the
+/// same name. Only the fixed size block allocator grows the heap and gives
+/// back its pages, the others get the whole heap up front, which is still
+/// backed on demand.
+#[cfg(feature = "bump-allocator")]
+pub type GlobalAllocator = bump::BumpAllocator;
+#[cfg(feature = "linked-list-allocator")]
+pub type GlobalAllocator = linked_list::LinkedListAllocator;
+#[cfg(not(any(feature = "bump-allocator", feature = "linked-list-allocator")))]
+pub type GlobalAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+/// The global allocator with its lock, if it needs one.
+#[cfg(any(feature = "bump-allocator", feature = "linked-list-allocator"))]
+type Backend = Locked<GlobalAllocator>;
+// locks each of its size classes on its own
+#[cfg(not(any(feature = "bump-allocator", feature = "linked-list-allocator")))]
+type Backend = GlobalAllocator;
+
+// only used to initialize the static
+#[cfg(any(feature = "bump-allocator", feature = "linked-list-allocator"))]
+#[allow(clippy::declare_interior_mutable_const)]
+const BACKEND: Backend = Locked::new(GlobalAllocator::new());
+#[cfg(not(any(feature = "bump-allocator", feature = "linked-list-allocator")))]
+#[allow(clippy::declare_interior_mutable_const)]
+const BACKEND: Backend = GlobalAllocator::new();
+
+/// With the `debug-allocator` feature, the heap is checked for corruption.
+/// Its statistics then include the red zones and the freed allocations the
+/// debug allocator holds back.
+#[cfg(feature = "debug-allocator")]
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
-
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
+static ALLOCATOR: debug::DebugAllocator<Backend> = debug::DebugAllocator::new(BACKEND);
+#[cfg(not(feature = "debug-allocator"))]
+#[global_allocator]
+static ALLOCATOR: Backend = BACKEND;
+
+#[cfg(feature = "debug-allocator")]
+fn backend() -> &'static Backend {
+    ALLOCATOR.inner()
+}
+
+#[cfg(not(feature = "debug-allocator"))]
+fn backend() -> &'static Backend {
+    &ALLOCATOR
+}
+
+#[cfg(any(feature = "bump-allocator", feature = "linked-list-allocator"))]
+fn global() -> spin::MutexGuard<'static, GlobalAllocator> {
+    backend().lock()
+}
+
+#[cfg(not(any(feature = "bump-allocator", feature = "linked-list-allocator")))]
+fn global() -> &'static GlobalAllocator {
+    backend()
+}
+
+/// Maps the heap's pages, created once the heap is initialized. Heap pages
+/// are only backed on demand afterwards. The heap has a mapper of its own, as
+/// it is used before and while `vmem::MANAGER` is locked. It is only locked
+/// with [`FRAMES`] held, so it is never contended.
+static MAPPER: spin::Once<spin::Mutex<OffsetPageTable<'static>>> = spin::Once::new();
+/// Bytes of the heap backed by frames.
+static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+static COMMIT_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_SIZE);
+
+/// Why a page fault couldn't be resolved by backing a heap page.
+#[derive(Debug)]
+pub enum HeapFault {
+    /// The fault isn't a missing heap page, or the heap isn't initialized.
+    OutsideHeap,
+    CommitLimitReached,
+    OutOfFrames,
+    /// The fault happened while the frame allocator was locked.
+    FramesLocked,
+    Map(MapToError<Size4KiB>),
+}
+
+/// Reserves the heap without mapping it. Its pages are backed with frames
+/// from [`FRAMES`] when they are first touched. The allocator starts with
+/// [`HEAP_INITIAL_SIZE`] bytes and grows when it runs out.
+///
+/// # Safety
+///
+/// The complete physical memory must be mapped at `physical_memory_offset`,
+/// the heap range must be unused and the page fault handler must be loaded.
+/// The heap's page table entries must not be changed through any other
+/// mapper.
+pub unsafe fn init_heap(physical_memory_offset: VirtAddr) {
+    MAPPER.call_once(|| spin::Mutex::new(memory::init(physical_memory_offset)));
+    #[cfg(not(any(feature = "bump-allocator", feature = "linked-list-allocator")))]
+    global().init(HEAP_START, HEAP_INITIAL_SIZE, HEAP_SIZE);
+    #[cfg(any(feature = "bump-allocator", feature = "linked-list-allocator"))]
+    global().init(HEAP_START, HEAP_SIZE);
+}
+
+/// Backs the heap page containing `addr` with a frame. Called by the page
+/// fault handler.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> Result<(), HeapFault> {
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let in_heap = (heap_start..(heap_start + HEAP_SIZE)).contains(&addr);
+    let mapper = match MAPPER.r#try() {
+        Some(mapper) if in_heap => mapper,
+        _ => return Err(HeapFault::OutsideHeap),
     };
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return Err(HeapFault::OutsideHeap);
+    }
 
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    let page_size = Size4KiB::SIZE as usize;
+    if COMMITTED.fetch_add(page_size, Ordering::Relaxed) + page_size
+        > COMMIT_LIMIT.load(Ordering::Relaxed)
+    {
+        COMMITTED.fetch_sub(page_size, Ordering::Relaxed);
+        return Err(HeapFault::CommitLimitReached);
     }
 
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    let res = map_heap_page(Page::containing_address(addr), mapper);
+    if res.is_err() {
+        COMMITTED.fetch_sub(page_size, Ordering::Relaxed);
     }
+    res
+}
+
+fn map_heap_page(
+    page: Page,
+    mapper: &spin::Mutex<OffsetPageTable<'static>>,
+) -> Result<(), HeapFault> {
+    // the fault could come from code holding the lock, which would never
+    // get to release it
+    let mut frames = FRAMES.try_lock().ok_or(HeapFault::FramesLocked)?;
+    let frame = frames.allocate_frame().ok_or(HeapFault::OutOfFrames)?;
+
+    // the handler runs with interrupts disabled and only changes the entries
+    // of the heap, which nothing else maps
+    let res = unsafe {
+        mapper.lock().map_to_with_table_flags(
+            page,
+            frame,
+            Attributes::new().flags(),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            &mut *frames,
+        )
+    };
+    match res {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(err) => {
+            unsafe { frames.deallocate_frame(frame) };
+            Err(HeapFault::Map(err))
+        }
+    }
+}
+
+/// Gives back the frames of the heap pages within `range`, which are backed
+/// again when they are touched next. Skipped if the frame allocator is
+/// locked, as the allocator can be called with the lock held, and outside
+/// the heap, where allocators under test may manage memory.
+fn decommit(range: Range<usize>) {
+    let Some(mapper) = MAPPER.r#try() else {
+        return;
+    };
+    if range.start < HEAP_START || range.end > HEAP_START + HEAP_SIZE {
+        return;
+    }
+    let start = x86_64::align_up(range.start as u64, Size4KiB::SIZE);
+    let end = x86_64::align_down(range.end as u64, Size4KiB::SIZE);
+    if start >= end {
+        return;
+    }
+    let Some(mut frames) = FRAMES.try_lock() else {
+        return;
+    };
+
+    let mut mapper = mapper.lock();
+    let pages = Page::range(
+        Page::<Size4KiB>::containing_address(VirtAddr::new(start)),
+        Page::containing_address(VirtAddr::new(end)),
+    );
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frames.deallocate_frame(frame) };
+            COMMITTED.fetch_sub(Size4KiB::SIZE as usize, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the current size of the heap, which grows up to [`HEAP_SIZE`].
+pub fn size() -> usize {
+    global().size()
+}
+
+/// Returns the statistics of the kernel heap.
+pub fn stats() -> stats::Stats {
+    global().stats()
+}
+
+/// Limits the bytes of the heap backed by frames. Pages backed already stay
+/// when the limit is lowered below [`committed`].
+pub fn set_commit_limit(bytes: usize) {
+    COMMIT_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+pub fn commit_limit() -> usize {
+    COMMIT_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Returns the bytes of the heap backed by frames.
+pub fn committed() -> usize {
+    COMMITTED.load(Ordering::Relaxed)
+}
 
-    Ok(())
+/// Dumps the statistics of the heap before panicking, as they tell whether
+/// it is exhausted or fragmented.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    // neither allocates
+    log::error!(
+        "out of memory, {} of {} KiB committed\n{}",
+        committed() / 1024,
+        commit_limit() / 1024,
+        stats()
+    );
+    panic!("allocation of {:?} failed", layout)
 }
 
 pub struct Dummy;
@@ -1,4 +1,5 @@
-use super::{align_up, Locked};
+use super::stats::Stats;
+use super::{align_up, tracking, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
@@ -7,6 +8,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    stats: Stats,
 }
 
 impl BumpAllocator {
@@ -17,6 +19,7 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            stats: Stats::new(&[]),
         }
     }
 
@@ -29,6 +32,22 @@ impl BumpAllocator {
         self.heap_end = heap_start.saturating_add(heap_size);
         self.next = heap_start;
     }
+
+    /// Returns the size of the heap.
+    pub fn size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    /// Returns what the allocator handed out so far.
+    pub fn stats(&self) -> Stats {
+        let free = self.heap_end - self.next;
+        Stats {
+            heap_size: self.size(),
+            heap_free: free,
+            largest_free: Some(free),
+            ..self.stats
+        }
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -37,21 +56,24 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
 
         let alloc_start = align_up(bump.next, layout.align());
         let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
+            Some(end) if end <= bump.heap_end => end,
+            _ => {
+                bump.stats.fail(None);
+                return ptr::null_mut(); // out of memory
+            }
         };
 
-        if alloc_end > bump.heap_end {
-            ptr::null_mut() // out of memory
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
-        }
+        bump.next = alloc_end;
+        bump.allocations += 1;
+        bump.stats.alloc(None, layout.size());
+        tracking::record_alloc(alloc_start as *mut u8, layout.size());
+        alloc_start as *mut u8
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock(); // get a mutable reference
+        bump.stats.free(None, layout.size());
+        tracking::record_free(ptr);
 
         bump.allocations -= 1;
         if bump.allocations == 0 {
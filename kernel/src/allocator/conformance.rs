@@ -0,0 +1,213 @@
+//! Checks every allocator has to pass, run by the `allocators` integration
+//! test in QEMU and by the host tests of the workspace. It only depends on
+//! `core` and `alloc`, so that the host can include it as is.
+//!
+//! Each check panics on failure and frees everything it allocated, so that
+//! the bump allocator starts over for the next one.
+
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::slice;
+
+/// The size of the heap the checks need.
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Runs all checks against `allocator`, which must be empty and manage at
+/// least [`HEAP_SIZE`] bytes.
+pub fn run<A: GlobalAlloc>(allocator: &A) {
+    alignment(allocator);
+    zeroed(allocator);
+    realloc(allocator);
+    fragmentation(allocator);
+    stress(allocator);
+}
+
+/// Allocations of all sizes and alignments are aligned and don't overlap.
+pub fn alignment<A: GlobalAlloc>(allocator: &A) {
+    let mut blocks = Vec::new();
+    for shift in 0..=12 {
+        for size in [1, 7, 8, 24, 100, 4096] {
+            let layout = Layout::from_size_align(size, 1 << shift).unwrap();
+            let block = Block::new(allocator, layout, blocks.len() as u8)
+                .unwrap_or_else(|| panic!("allocating {:?} failed", layout));
+            assert_eq!(block.ptr as usize % layout.align(), 0, "{:?}", layout);
+            blocks.push(block);
+        }
+    }
+
+    for block in blocks {
+        block.free(allocator);
+    }
+}
+
+/// `alloc_zeroed` clears memory that was used before.
+pub fn zeroed<A: GlobalAlloc>(allocator: &A) {
+    for size in [8, 200, 5000, 70_000] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        Block::new(allocator, layout, 0xff).unwrap().free(allocator);
+
+        let ptr = unsafe { allocator.alloc_zeroed(layout) };
+        assert!(!ptr.is_null());
+        let bytes = unsafe { slice::from_raw_parts(ptr, size) };
+        assert!(bytes.iter().all(|&b| b == 0), "{} bytes not zeroed", size);
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+}
+
+/// Growing and shrinking keeps the contents and the alignment.
+pub fn realloc<A: GlobalAlloc>(allocator: &A) {
+    let mut block = Block::new(allocator, Layout::from_size_align(16, 64).unwrap(), 1).unwrap();
+    for size in [3000, 100_000, 10, 1, 600] {
+        block = block.realloc(allocator, size);
+        assert_eq!(block.ptr as usize % 64, 0);
+    }
+    block.free(allocator);
+}
+
+/// Memory freed between live blocks can be used again, and freeing
+/// everything leaves room for one large block.
+pub fn fragmentation<A: GlobalAlloc>(allocator: &A) {
+    let mut blocks = Vec::new();
+    for i in 0..256 {
+        let layout = Layout::from_size_align(16 << (i % 8), 8).unwrap();
+        blocks.push(Block::new(allocator, layout, i as u8).unwrap());
+    }
+
+    // free every other block and fill the holes with differently sized ones
+    let mut survivors = Vec::new();
+    for (i, block) in blocks.into_iter().enumerate() {
+        if i % 2 == 0 {
+            block.free(allocator);
+        } else {
+            survivors.push(block);
+        }
+    }
+    for i in 0..128 {
+        let layout = Layout::from_size_align(24 << (i % 6), 16).unwrap();
+        survivors.push(Block::new(allocator, layout, i as u8).unwrap());
+    }
+
+    for block in survivors {
+        block.free(allocator);
+    }
+
+    let large = Layout::from_size_align(HEAP_SIZE / 2, 4096).unwrap();
+    Block::new(allocator, large, 7)
+        .expect("freed memory wasn't given back")
+        .free(allocator);
+}
+
+/// A random mix of allocations, reallocations and frees with content checks.
+/// Allocations may fail when the heap is full, but must never overlap.
+pub fn stress<A: GlobalAlloc>(allocator: &A) {
+    const SLOTS: usize = 128;
+
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut slots: Vec<Option<Block>> = (0..SLOTS).map(|_| None).collect();
+    for _ in 0..10_000 {
+        let i = rng.next() as usize % SLOTS;
+        let size = match rng.next() % 16 {
+            0 => 1 + rng.next() as usize % (64 * 1024),
+            _ => 1 + rng.next() as usize % 2048,
+        };
+
+        slots[i] = match slots[i].take() {
+            None => {
+                let layout = Layout::from_size_align(size, 1 << (rng.next() % 7)).unwrap();
+                Block::new(allocator, layout, i as u8)
+            }
+            Some(block) if rng.next() % 4 == 0 => Some(block.realloc(allocator, size)),
+            Some(block) => {
+                block.free(allocator);
+                None
+            }
+        };
+    }
+
+    for block in slots.into_iter().flatten() {
+        block.free(allocator);
+    }
+}
+
+/// An allocation filled with a pattern that is checked when it is freed.
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+    seed: u8,
+}
+
+impl Block {
+    fn new<A: GlobalAlloc>(allocator: &A, layout: Layout, seed: u8) -> Option<Block> {
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        let mut block = Block { ptr, layout, seed };
+        block.fill(0);
+        Some(block)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn fill(&mut self, from: usize) {
+        let bytes = unsafe { slice::from_raw_parts_mut(self.ptr, self.layout.size()) };
+        for (i, byte) in bytes.iter_mut().enumerate().skip(from) {
+            *byte = pattern(self.seed, i);
+        }
+    }
+
+    /// Panics if the pattern was overwritten in the first `len` bytes.
+    fn check(&self, len: usize) {
+        for (i, &byte) in self.bytes()[..len].iter().enumerate() {
+            assert_eq!(
+                byte,
+                pattern(self.seed, i),
+                "byte {} of {:?} at {:p} overwritten",
+                i,
+                self.layout,
+                self.ptr
+            );
+        }
+    }
+
+    /// Resizes the block, keeping the block if it fails.
+    fn realloc<A: GlobalAlloc>(self, allocator: &A, size: usize) -> Block {
+        self.check(self.layout.size());
+        let ptr = unsafe { allocator.realloc(self.ptr, self.layout, size) };
+        if ptr.is_null() {
+            return self;
+        }
+
+        let mut block = Block {
+            ptr,
+            layout: Layout::from_size_align(size, self.layout.align()).unwrap(),
+            seed: self.seed,
+        };
+        let kept = size.min(self.layout.size());
+        block.check(kept);
+        block.fill(kept);
+        block
+    }
+
+    fn free<A: GlobalAlloc>(self, allocator: &A) {
+        self.check(self.layout.size());
+        unsafe { allocator.dealloc(self.ptr, self.layout) };
+    }
+}
+
+fn pattern(seed: u8, i: usize) -> u8 {
+    seed ^ (i as u8).wrapping_mul(31) ^ (i >> 8) as u8
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
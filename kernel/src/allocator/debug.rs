@@ -0,0 +1,273 @@
+//! A wrapper around another allocator that catches heap corruption. The
+//! kernel heap uses it with the `debug-allocator` feature.
+//!
+//! Every allocation is surrounded by red zones, which are checked when it is
+//! freed. Freed memory is poisoned and held back in a quarantine, which is
+//! checked on every allocation, so that double frees and writes to freed
+//! memory are caught before the memory is used again. Errors are logged with
+//! the layout of the allocation, whose memory is leaked rather than freed.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::error;
+use spin::Mutex;
+
+/// The least bytes in front of and behind each allocation that must not be
+/// written.
+const RED_ZONE: usize = 16;
+const RED_ZONE_BYTE: u8 = 0xfd;
+/// Fills new allocations, so that reads of uninitialized memory stand out.
+const UNINIT_BYTE: u8 = 0xcd;
+const FREED_BYTE: u8 = 0xdd;
+/// Marks the header of allocations that weren't freed yet.
+const LIVE: usize = 0x11fe_11fe_11fe_11fe;
+const FREED: usize = 0xdead_dead_dead_dead;
+/// How many freed allocations are held back, and with how many bytes at
+/// most. Larger allocations are freed right away.
+const QUARANTINE_LEN: usize = 32;
+const QUARANTINE_BYTES: usize = 32 * 1024;
+
+/// Kinds of heap corruption the [`DebugAllocator`] detects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// The allocation was freed before. Only detected while it is still
+    /// in the quarantine; once its memory is handed out again, a second free
+    /// is reported as [`InvalidFree`](Self::InvalidFree), or frees the block
+    /// that now starts there without an error.
+    DoubleFree,
+    /// The pointer was never allocated, or the header in front of it was
+    /// overwritten.
+    InvalidFree,
+    /// The allocation was freed with another size than it was allocated.
+    SizeMismatch,
+    /// Bytes in front of the allocation were overwritten.
+    Underflow,
+    /// Bytes behind the allocation were overwritten.
+    Overflow,
+    /// Freed memory was written to.
+    UseAfterFree,
+}
+
+/// Kept in front of the front red zone.
+#[repr(C)]
+struct Header {
+    state: usize,
+    size: usize,
+}
+
+const HEADER: usize = mem::size_of::<Header>();
+
+/// A heap error and the allocation it was found in.
+type Report = (HeapError, usize, Layout);
+
+pub struct DebugAllocator<A> {
+    inner: A,
+    quarantine: Mutex<Quarantine>,
+    errors: AtomicUsize,
+    last_error: Mutex<Option<HeapError>>,
+}
+
+impl<A: GlobalAlloc> DebugAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        DebugAllocator {
+            inner,
+            quarantine: Mutex::new(Quarantine::new()),
+            errors: AtomicUsize::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Returns the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns how many errors were found.
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<HeapError> {
+        *self.last_error.lock()
+    }
+
+    /// Checks the freed allocations in the quarantine for writes.
+    pub fn check(&self) {
+        let report = self.quarantine.lock().check();
+        self.report(report);
+    }
+
+    /// Logs an error. The quarantine must not be locked, so that a logger
+    /// can allocate.
+    fn report(&self, report: Option<Report>) {
+        if let Some((err, ptr, layout)) = report {
+            error!("heap: {:?} of {:?} at {:#x}", err, layout, ptr);
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            *self.last_error.lock() = Some(err);
+        }
+    }
+
+    /// Returns the header of the allocation at `ptr`, checking that it is
+    /// live and was allocated with `layout`.
+    unsafe fn header(&self, ptr: *mut u8, layout: Layout) -> Result<*mut Header, HeapError> {
+        let front = front(layout);
+        if (ptr as usize) < front {
+            return Err(HeapError::InvalidFree);
+        }
+        let header = ptr.sub(front) as *mut Header;
+        match (*header).state {
+            LIVE if (*header).size == layout.size() => Ok(header),
+            LIVE => Err(HeapError::SizeMismatch),
+            FREED => Err(HeapError::DoubleFree),
+            _ => Err(HeapError::InvalidFree),
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.check();
+
+        let Some(outer) = outer(layout) else {
+            return core::ptr::null_mut();
+        };
+        let block = self.inner.alloc(outer);
+        if block.is_null() {
+            return block;
+        }
+
+        let front = front(layout);
+        let ptr = block.add(front);
+        block.cast::<Header>().write(Header {
+            state: LIVE,
+            size: layout.size(),
+        });
+        block.add(HEADER).write_bytes(RED_ZONE_BYTE, front - HEADER);
+        ptr.write_bytes(UNINIT_BYTE, layout.size());
+        ptr.add(layout.size()).write_bytes(RED_ZONE_BYTE, RED_ZONE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = match self.header(ptr, layout) {
+            Ok(header) => header,
+            Err(err) => return self.report(Some((err, ptr as usize, layout))),
+        };
+
+        let front_zone = front(layout) - HEADER;
+        let before = slice::from_raw_parts(ptr.sub(front_zone), front_zone);
+        let after = slice::from_raw_parts(ptr.add(layout.size()), RED_ZONE);
+        if !before.iter().all(|&b| b == RED_ZONE_BYTE) {
+            self.report(Some((HeapError::Underflow, ptr as usize, layout)));
+        }
+        if !after.iter().all(|&b| b == RED_ZONE_BYTE) {
+            self.report(Some((HeapError::Overflow, ptr as usize, layout)));
+        }
+
+        (*header).state = FREED;
+        ptr.write_bytes(FREED_BYTE, layout.size());
+        if layout.size() > QUARANTINE_BYTES {
+            self.inner.dealloc(header.cast(), outer(layout).unwrap());
+            return;
+        }
+
+        let mut reports = [None; QUARANTINE_LEN];
+        {
+            let mut quarantine = self.quarantine.lock();
+            let mut evicted = 0;
+            while quarantine.len == QUARANTINE_LEN
+                || quarantine.bytes + layout.size() > QUARANTINE_BYTES
+            {
+                let (ptr, layout) = quarantine.pop();
+                match poisoned(ptr, layout) {
+                    Ok(()) => self
+                        .inner
+                        .dealloc((ptr - front(layout)) as *mut u8, outer(layout).unwrap()),
+                    Err(report) => reports[evicted] = Some(report),
+                }
+                evicted += 1;
+            }
+            quarantine.push(ptr as usize, layout);
+        }
+        for report in reports {
+            self.report(report);
+        }
+    }
+}
+
+/// Freed allocations that weren't given back to the wrapped allocator yet,
+/// oldest first.
+struct Quarantine {
+    entries: [(usize, Layout); QUARANTINE_LEN],
+    len: usize,
+    bytes: usize,
+}
+
+impl Quarantine {
+    const fn new() -> Self {
+        Quarantine {
+            entries: [(0, Layout::new::<()>()); QUARANTINE_LEN],
+            len: 0,
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, ptr: usize, layout: Layout) {
+        self.entries[self.len] = (ptr, layout);
+        self.len += 1;
+        self.bytes += layout.size();
+    }
+
+    fn pop(&mut self) -> (usize, Layout) {
+        let oldest = self.entries[0];
+        self.entries.copy_within(1..self.len, 0);
+        self.len -= 1;
+        self.bytes -= oldest.1.size();
+        oldest
+    }
+
+    /// Returns the first allocation that was written to since it was freed,
+    /// and leaves it out of the quarantine for good.
+    fn check(&mut self) -> Option<Report> {
+        let i = self.entries[..self.len]
+            .iter()
+            .position(|&(ptr, layout)| unsafe { poisoned(ptr, layout) }.is_err())?;
+        let (ptr, layout) = self.entries[i];
+        self.entries.copy_within(i + 1..self.len, i);
+        self.len -= 1;
+        self.bytes -= layout.size();
+        Some((HeapError::UseAfterFree, ptr, layout))
+    }
+}
+
+/// Checks that the freed allocation at `ptr` still holds the poison.
+unsafe fn poisoned(ptr: usize, layout: Layout) -> Result<(), Report> {
+    let bytes = slice::from_raw_parts(ptr as *const u8, layout.size());
+    // compare whole words where possible, as this runs on every allocation
+    let (head, words, tail) = bytes.align_to::<u64>();
+    let word = u64::from_ne_bytes([FREED_BYTE; 8]);
+    if head.iter().chain(tail).all(|&b| b == FREED_BYTE) && words.iter().all(|&w| w == word) {
+        Ok(())
+    } else {
+        Err((HeapError::UseAfterFree, ptr, layout))
+    }
+}
+
+/// Returns the bytes in front of an allocation, which hold the header and
+/// the front red zone.
+fn front(layout: Layout) -> usize {
+    super::align_up(
+        HEADER + RED_ZONE,
+        layout.align().max(mem::align_of::<Header>()),
+    )
+}
+
+/// Returns the layout of an allocation with its header and red zones.
+fn outer(layout: Layout) -> Option<Layout> {
+    let size = front(layout)
+        .checked_add(layout.size())?
+        .checked_add(RED_ZONE)?;
+    Layout::from_size_align(size, layout.align().max(mem::align_of::<Header>())).ok()
+}
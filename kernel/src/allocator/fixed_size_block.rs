@@ -1,15 +1,29 @@
-use super::Locked;
+use super::stats::{Counters, Stats, MAX_CLASSES};
+use super::{align_up, tracking};
 use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::{
     mem,
     ptr::{self, NonNull},
 };
+use spin::Mutex;
 
 /// The block sizes to use.
 ///
 /// The sizes must each be power of 2 because they are also used as
 /// the block alignment (alignments must be always powers of 2).
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+const _: () = assert!(BLOCK_SIZES.len() <= MAX_CLASSES);
+
+/// The smallest size of a slab.
+const SLAB_SIZE: usize = 4096;
+
+/// The fallback heap grows by multiples of this.
+const GROWTH: usize = 1024 * 1024;
+
+/// Fallback allocations at least this large give their pages back when
+/// they are freed.
+const DECOMMIT_SIZE: usize = 64 * 1024;
 
 /// Choose an appropriate block size for the given layout.
 ///
@@ -19,84 +33,359 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+/// Returns the size of the slabs for blocks of `block_size`, which is also
+/// their alignment, so that the slab of a block is found from its address.
+fn slab_layout(block_size: usize) -> Layout {
+    let size = (block_size * 8).max(SLAB_SIZE);
+    Layout::from_size_align(size, size).unwrap()
+}
+
 struct ListNode {
-    next: Option<&'static mut ListNode>,
+    next: *mut ListNode,
 }
 
+/// The header at the start of a slab, followed by its blocks.
+struct Slab {
+    /// Blocks of the slab that were freed.
+    free: *mut ListNode,
+    /// The first block that was never handed out.
+    unused: usize,
+    /// The number of blocks handed out.
+    used: usize,
+    /// The neighbours in the list of partial slabs.
+    prev: *mut Slab,
+    next: *mut Slab,
+}
+
+/// The slabs of one block size.
+struct SizeClass {
+    block_size: usize,
+    /// Slabs with free blocks, the first of which blocks are taken from.
+    partial: *mut Slab,
+    /// An empty slab that is kept, so that a block allocated and freed over
+    /// and over doesn't take and give back a slab each time.
+    spare: *mut Slab,
+    /// Free blocks in the partial slabs and the spare one.
+    free_blocks: u64,
+    counters: Counters,
+}
+
+// slabs are only reached through the lock of their size class
+unsafe impl Send for SizeClass {}
+
+impl SizeClass {
+    const fn new(block_size: usize) -> Self {
+        SizeClass {
+            block_size,
+            partial: ptr::null_mut(),
+            spare: ptr::null_mut(),
+            free_blocks: 0,
+            counters: Counters::new(),
+        }
+    }
+
+    fn first_block(&self, slab: *mut Slab) -> usize {
+        slab as usize + align_up(mem::size_of::<Slab>(), self.block_size)
+    }
+
+    fn capacity(&self) -> u64 {
+        let slab_size = slab_layout(self.block_size).size();
+        ((slab_size - align_up(mem::size_of::<Slab>(), self.block_size)) / self.block_size) as u64
+    }
+
+    /// Takes a block from the first partial slab, or from the spare slab if
+    /// there is none. Returns `None` if there is neither.
+    unsafe fn alloc(&mut self) -> Option<*mut u8> {
+        if self.partial.is_null() {
+            if self.spare.is_null() {
+                return None;
+            }
+            let spare = mem::replace(&mut self.spare, ptr::null_mut());
+            self.push(spare);
+        }
+
+        let slab = self.partial;
+        let block = if (*slab).free.is_null() {
+            let block = (*slab).unused;
+            (*slab).unused += self.block_size;
+            block as *mut u8
+        } else {
+            let node = (*slab).free;
+            (*slab).free = (*node).next;
+            node as *mut u8
+        };
+        (*slab).used += 1;
+        self.free_blocks -= 1;
+
+        if self.is_full(slab) {
+            self.remove(slab);
+        }
+        Some(block)
+    }
+
+    /// Starts a slab at `addr`, whose blocks are handed out as needed.
+    unsafe fn add_slab(&mut self, addr: *mut u8) {
+        let slab = addr as *mut Slab;
+        slab.write(Slab {
+            free: ptr::null_mut(),
+            unused: self.first_block(slab),
+            used: 0,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        });
+        self.free_blocks += self.capacity();
+        self.push(slab);
+    }
+
+    /// Puts `block` back into its slab. Returns the slab if it became empty
+    /// and isn't kept as the spare one, so that the caller releases it.
+    unsafe fn dealloc(&mut self, block: *mut u8) -> Option<*mut u8> {
+        let slab_size = slab_layout(self.block_size).size();
+        let slab = (block as usize & !(slab_size - 1)) as *mut Slab;
+        let was_full = self.is_full(slab);
+
+        let node = block as *mut ListNode;
+        node.write(ListNode { next: (*slab).free });
+        (*slab).free = node;
+        (*slab).used -= 1;
+        self.free_blocks += 1;
+        if was_full {
+            self.push(slab);
+        }
+
+        if (*slab).used > 0 {
+            return None;
+        }
+        self.remove(slab);
+        if self.spare.is_null() {
+            self.spare = slab;
+            return None;
+        }
+        self.free_blocks -= self.capacity();
+        Some(slab as *mut u8)
+    }
+
+    unsafe fn is_full(&self, slab: *mut Slab) -> bool {
+        let slab_end = slab as usize + slab_layout(self.block_size).size();
+        (*slab).free.is_null() && (*slab).unused + self.block_size > slab_end
+    }
+
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = self.partial;
+        if !self.partial.is_null() {
+            (*self.partial).prev = slab;
+        }
+        self.partial = slab;
+    }
+
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        let (prev, next) = ((*slab).prev, (*slab).next);
+        if prev.is_null() {
+            self.partial = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
+
+/// The heap slabs and large allocations come from.
+struct Backing {
+    heap: linked_list_allocator::Heap,
+    /// The size the heap may grow to.
+    max_size: usize,
+    /// Slabs and large allocations.
+    fallback: Counters,
+    /// Allocations too large for a block.
+    large: Counters,
+}
+
+impl Backing {
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        loop {
+            match self.heap.allocate_first_fit(layout) {
+                Ok(ptr) => {
+                    self.fallback.alloc(layout.size());
+                    return ptr.as_ptr();
+                }
+                Err(_) if self.grow(layout) => {}
+                Err(_) => return ptr::null_mut(),
+            }
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.heap.deallocate(NonNull::new(ptr).unwrap(), layout);
+        self.fallback.free(layout.size());
+
+        if layout.size() >= DECOMMIT_SIZE {
+            // the freed block starts with the header of a hole
+            let start = ptr as usize + 2 * mem::size_of::<usize>();
+            super::decommit(start..(ptr as usize + layout.size()));
+        }
+    }
+
+    /// Extends the heap by enough to fit `layout`, however much free memory
+    /// is left at its end.
+    fn grow(&mut self, layout: Layout) -> bool {
+        let needed = align_up(layout.size() + layout.align(), GROWTH);
+        let by = needed.min(self.max_size - self.heap.size());
+        if by == 0 {
+            return false;
+        }
+
+        unsafe { self.heap.extend(by) };
+        true
+    }
+}
+
+/// Hands out blocks of fixed sizes from slabs, which are taken from the
+/// backing heap in one piece and given back once all their blocks are free.
+///
+/// Each size class has a lock of its own, so that allocations of different
+/// sizes don't wait for each other, and only take the lock of the backing
+/// heap to get or release a slab.
 pub struct FixedSizeBlockAllocator {
-    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap,
+    classes: [Mutex<SizeClass>; BLOCK_SIZES.len()],
+    backing: Mutex<Backing>,
+    /// Bytes handed out and the most that ever were.
+    bytes: AtomicU64,
+    peak_bytes: AtomicU64,
 }
 
 impl FixedSizeBlockAllocator {
     /// Creates an empty FixedSizeBlockAllocator.
     pub const fn new() -> Self {
-        const EMPTY: Option<&'static mut ListNode> = None;
+        // only copied to initialize the array
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: Mutex<SizeClass> = Mutex::new(SizeClass::new(0));
+        let mut classes = [EMPTY; BLOCK_SIZES.len()];
+        let mut i = 0;
+        while i < BLOCK_SIZES.len() {
+            classes[i] = Mutex::new(SizeClass::new(BLOCK_SIZES[i]));
+            i += 1;
+        }
+
         FixedSizeBlockAllocator {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            classes,
+            backing: Mutex::new(Backing {
+                heap: linked_list_allocator::Heap::empty(),
+                max_size: 0,
+                fallback: Counters::new(),
+                large: Counters::new(),
+            }),
+            bytes: AtomicU64::new(0),
+            peak_bytes: AtomicU64::new(0),
         }
     }
 
-    /// Initialize the allocator with the given heap bounds.
+    /// Initialize the allocator with the given heap bounds. The heap starts
+    /// with `heap_size` bytes and grows up to `max_size` when it runs out.
     ///
     /// This function is unsafe because the caller must guarantee that the given
     /// heap bounds are valid and that the heap is unused. This method must be
     /// called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.fallback_allocator.init(heap_start, heap_size);
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize, max_size: usize) {
+        let mut backing = self.backing.lock();
+        backing.heap.init(heap_start, heap_size);
+        backing.max_size = max_size;
+    }
+
+    /// Returns the current size of the heap.
+    pub fn size(&self) -> usize {
+        self.backing.lock().heap.size()
     }
 
-    /// Allocates using the fallback allocator.
-    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+    /// Returns what the allocator handed out so far.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new(BLOCK_SIZES);
+        let mut cached = 0;
+        for (i, class) in self.classes.iter().enumerate() {
+            let class = class.lock();
+            stats.classes[i] = class.counters;
+            stats.cached[i] = class.free_blocks;
+            stats.total += class.counters;
+            cached += class.free_blocks as usize * class.block_size;
         }
+
+        let backing = self.backing.lock();
+        stats.total += backing.large;
+        stats.fallback = backing.fallback;
+        stats.peak_bytes = self.peak_bytes.load(Ordering::Relaxed);
+        stats.heap_size = backing.heap.size();
+        stats.heap_free = backing.heap.free() + cached;
+        stats
+    }
+
+    /// Allocates a block from the slabs of size class `index`.
+    fn alloc_block(&self, index: usize, size: usize) -> *mut u8 {
+        let mut class = self.classes[index].lock();
+        let block = match unsafe { class.alloc() } {
+            Some(block) => block,
+            None => {
+                let slab = self.backing.lock().alloc(slab_layout(class.block_size));
+                if slab.is_null() {
+                    class.counters.failures += 1;
+                    return ptr::null_mut();
+                }
+                unsafe {
+                    class.add_slab(slab);
+                    class.alloc().unwrap()
+                }
+            }
+        };
+        class.counters.alloc(size);
+        block
     }
 }
 
-unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+unsafe impl GlobalAlloc for FixedSizeBlockAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                match allocator.list_heads[index].take() {
-                    Some(node) => {
-                        allocator.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
-                    }
-                    None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        allocator.fallback_alloc(layout)
-                    }
+        let ptr = match list_index(&layout) {
+            Some(index) => self.alloc_block(index, layout.size()),
+            None => {
+                let mut backing = self.backing.lock();
+                let ptr = backing.alloc(layout);
+                if ptr.is_null() {
+                    backing.large.failures += 1;
+                } else {
+                    backing.large.alloc(layout.size());
                 }
+                ptr
             }
-            None => allocator.fallback_alloc(layout),
+        };
+
+        if !ptr.is_null() {
+            let size = layout.size() as u64;
+            let bytes = self.bytes.fetch_add(size, Ordering::Relaxed) + size;
+            self.peak_bytes.fetch_max(bytes, Ordering::Relaxed);
+            tracking::record_alloc(ptr, layout.size());
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
+        self.bytes
+            .fetch_sub(layout.size() as u64, Ordering::Relaxed);
+        tracking::record_free(ptr);
+
         match list_index(&layout) {
             Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing node
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                let mut class = self.classes[index].lock();
+                class.counters.free(layout.size());
+                if let Some(slab) = class.dealloc(ptr) {
+                    self.backing
+                        .lock()
+                        .dealloc(slab, slab_layout(class.block_size));
+                }
             }
             None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+                let mut backing = self.backing.lock();
+                backing.large.free(layout.size());
+                backing.dealloc(ptr, layout);
             }
         }
     }
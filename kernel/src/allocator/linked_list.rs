@@ -1,4 +1,5 @@
-use super::{align_up, Locked};
+use super::stats::Stats;
+use super::{align_up, tracking, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr};
 
@@ -21,8 +22,12 @@ impl ListNode {
     }
 }
 
+/// Hands out memory from a list of free regions, sorted by address so that
+/// neighbouring regions are merged when memory is freed.
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_size: usize,
+    stats: Stats,
 }
 
 impl LinkedListAllocator {
@@ -30,6 +35,8 @@ impl LinkedListAllocator {
     pub const fn new() -> Self {
         Self {
             head: ListNode::new(0),
+            heap_size: 0,
+            stats: Stats::new(&[]),
         }
     }
 
@@ -40,20 +47,104 @@ impl LinkedListAllocator {
     /// called only once.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.add_free_region(heap_start, heap_size);
+        self.heap_size = heap_size;
     }
 
-    /// Adds the given memory region to the front of the list.
+    /// Returns the size of the heap.
+    pub fn size(&self) -> usize {
+        self.heap_size
+    }
+
+    /// Returns what the allocator handed out so far.
+    pub fn stats(&self) -> Stats {
+        let mut free = 0;
+        let mut largest = 0;
+        let mut current = &self.head;
+        while let Some(ref region) = current.next {
+            free += region.size;
+            largest = largest.max(region.size);
+            current = region;
+        }
+        Stats {
+            heap_size: self.heap_size,
+            heap_free: free,
+            largest_free: Some(largest),
+            ..self.stats
+        }
+    }
+
+    /// Adds the given memory region to the list, which is kept sorted by
+    /// address, and merges it with the free regions right before and after.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr)
+        // find the last region before the freed one
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|n| n.start_addr() < addr) {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let (size, next) = match current.next.take() {
+            Some(next) if addr + size == next.start_addr() => (size + next.size, next.next.take()),
+            next => (size, next),
+        };
+        // the head is no region and has a size of 0
+        if current.size > 0 && current.end_addr() == addr {
+            current.size += size;
+            current.next = next;
+        } else {
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(ListNode { size, next });
+            current.next = Some(&mut *node_ptr);
+        }
+    }
+
+    /// Resizes the allocation at `addr` from `size` to `new_size` bytes,
+    /// both adjusted by [`size_align`], without moving it. Shrinking frees the
+    /// end, growing takes from a free region right after the allocation.
+    ///
+    /// Returns whether the allocation could be resized.
+    ///
+    /// [`size_align`]: Self::size_align
+    unsafe fn resize_in_place(&mut self, addr: usize, size: usize, new_size: usize) -> bool {
+        let node_size = mem::size_of::<ListNode>();
+        if new_size <= size {
+            let rest = size - new_size;
+            if rest > 0 && rest < node_size {
+                // the rest would be lost
+                return false;
+            }
+            if rest > 0 {
+                self.add_free_region(addr + new_size, rest);
+            }
+            return true;
+        }
+
+        let end = addr + size;
+        let needed = new_size - size;
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|n| n.start_addr() < end) {
+            current = current.next.as_mut().unwrap();
+        }
+        match current.next.take() {
+            Some(next)
+                if next.start_addr() == end
+                    && (next.size == needed || next.size >= needed + node_size) =>
+            {
+                let rest = next.size - needed;
+                current.next = next.next.take();
+                if rest > 0 {
+                    self.add_free_region(end + needed, rest);
+                }
+                true
+            }
+            next => {
+                current.next = next;
+                false
+            }
+        }
     }
 
     /// Looks for a free region with the given size and alignment and removes
@@ -85,7 +176,13 @@ impl LinkedListAllocator {
     ///
     /// Returns the allocation start address on success.
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start > region.start_addr()
+            && alloc_start - region.start_addr() < mem::size_of::<ListNode>()
+        {
+            // the padding in front stays free, so it must fit a ListNode
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -126,12 +223,18 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
 
         if let Some((region, alloc_start)) = allocator.find_region(size, align) {
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+            if alloc_start > region_start {
+                allocator.add_free_region(region_start, alloc_start - region_start);
+            }
+            if region_end > alloc_end {
+                allocator.add_free_region(alloc_end, region_end - alloc_end);
             }
+            allocator.stats.alloc(None, layout.size());
+            tracking::record_alloc(alloc_start as *mut u8, layout.size());
             alloc_start as *mut u8
         } else {
+            allocator.stats.fail(None);
             ptr::null_mut()
         }
     }
@@ -140,6 +243,32 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         // perform layout adjustments
         let (size, _) = LinkedListAllocator::size_align(layout);
 
-        self.lock().add_free_region(ptr as usize, size)
+        let mut allocator = self.lock();
+        allocator.stats.free(None, layout.size());
+        tracking::record_free(ptr);
+        allocator.add_free_region(ptr as usize, size)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (size, _) = LinkedListAllocator::size_align(layout);
+        let (adjusted_new_size, _) = LinkedListAllocator::size_align(new_layout);
+
+        let mut allocator = self.lock();
+        if allocator.resize_in_place(ptr as usize, size, adjusted_new_size) {
+            allocator.stats.free(None, layout.size());
+            allocator.stats.alloc(None, new_size);
+            tracking::record_free(ptr);
+            tracking::record_alloc(ptr, new_size);
+            return ptr;
+        }
+        drop(allocator);
+
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
     }
 }
@@ -0,0 +1,181 @@
+use core::fmt;
+use core::ops::AddAssign;
+
+/// Number of block sizes the statistics have room for.
+pub const MAX_CLASSES: usize = 9;
+
+/// Counts allocations of one kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub allocations: u64,
+    pub frees: u64,
+    /// Allocations that returned null.
+    pub failures: u64,
+    /// Requested bytes of the allocations that weren't freed yet.
+    pub bytes: u64,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters {
+            allocations: 0,
+            frees: 0,
+            failures: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Returns the number of allocations that weren't freed yet.
+    pub fn live(&self) -> u64 {
+        self.allocations - self.frees
+    }
+
+    pub(super) fn alloc(&mut self, size: usize) {
+        self.allocations += 1;
+        self.bytes += size as u64;
+    }
+
+    pub(super) fn free(&mut self, size: usize) {
+        self.frees += 1;
+        self.bytes -= size as u64;
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, other: Counters) {
+        self.allocations += other.allocations;
+        self.frees += other.frees;
+        self.failures += other.failures;
+        self.bytes += other.bytes;
+    }
+}
+
+/// A snapshot of what an allocator handed out. Fixed in size, so that it
+/// can be taken without allocating.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub total: Counters,
+    /// The most bytes that were allocated at once.
+    pub peak_bytes: u64,
+    /// The block sizes of the allocator, if it has any.
+    pub class_sizes: &'static [usize],
+    pub classes: [Counters; MAX_CLASSES],
+    /// Freed blocks of each size that are kept for reuse.
+    pub cached: [u64; MAX_CLASSES],
+    /// Allocations passed on to the fallback allocator, including the ones
+    /// that make up new blocks.
+    pub fallback: Counters,
+    pub heap_size: usize,
+    /// Bytes of the heap that aren't handed out, including cached blocks.
+    pub heap_free: usize,
+    /// The largest free region, if the allocator knows it.
+    pub largest_free: Option<usize>,
+}
+
+impl Stats {
+    pub const fn new(class_sizes: &'static [usize]) -> Self {
+        Stats {
+            total: Counters::new(),
+            peak_bytes: 0,
+            class_sizes,
+            classes: [Counters::new(); MAX_CLASSES],
+            cached: [0; MAX_CLASSES],
+            fallback: Counters::new(),
+            heap_size: 0,
+            heap_free: 0,
+            largest_free: None,
+        }
+    }
+
+    pub(super) fn alloc(&mut self, class: Option<usize>, size: usize) {
+        self.total.alloc(size);
+        self.peak_bytes = self.peak_bytes.max(self.total.bytes);
+        if let Some(class) = class {
+            self.classes[class].alloc(size);
+        }
+    }
+
+    pub(super) fn free(&mut self, class: Option<usize>, size: usize) {
+        self.total.free(size);
+        if let Some(class) = class {
+            self.classes[class].free(size);
+        }
+    }
+
+    pub(super) fn fail(&mut self, class: Option<usize>) {
+        self.total.failures += 1;
+        if let Some(class) = class {
+            self.classes[class].failures += 1;
+        }
+    }
+
+    /// Returns the share of free memory outside of the largest free region,
+    /// which is 0 if all free memory could be handed out at once.
+    pub fn fragmentation(&self) -> Option<f64> {
+        let largest = self.largest_free?;
+        if self.heap_free == 0 {
+            return Some(0.0);
+        }
+        Some(1.0 - largest as f64 / self.heap_free as f64)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "heap: {} KiB, {} KiB free, {} KiB peak",
+            self.heap_size / 1024,
+            self.heap_free / 1024,
+            self.peak_bytes / 1024,
+        )?;
+        if let Some(fragmentation) = self.fragmentation() {
+            writeln!(f, "fragmentation: {:.1}%", fragmentation * 100.0)?;
+        }
+
+        writeln!(
+            f,
+            "{:>10} {:>10} {:>10} {:>10} {:>12} {:>8}",
+            "class", "allocs", "frees", "live", "bytes", "cached"
+        )?;
+        let classes = self.class_sizes.iter().zip(&self.classes).zip(&self.cached);
+        for ((size, counters), cached) in classes {
+            writeln!(
+                f,
+                "{:>10} {:>10} {:>10} {:>10} {:>12} {:>8}",
+                size,
+                counters.allocations,
+                counters.frees,
+                counters.live(),
+                counters.bytes,
+                cached
+            )?;
+        }
+        if !self.class_sizes.is_empty() {
+            let c = &self.fallback;
+            writeln!(
+                f,
+                "{:>10} {:>10} {:>10} {:>10} {:>12}",
+                "fallback",
+                c.allocations,
+                c.frees,
+                c.live(),
+                c.bytes
+            )?;
+        }
+        let c = &self.total;
+        writeln!(
+            f,
+            "{:>10} {:>10} {:>10} {:>10} {:>12}",
+            "total",
+            c.allocations,
+            c.frees,
+            c.live(),
+            c.bytes
+        )?;
+        if c.failures > 0 {
+            writeln!(f, "{} allocations failed", c.failures)?;
+        }
+        Ok(())
+    }
+}
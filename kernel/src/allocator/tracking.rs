@@ -0,0 +1,170 @@
+//! Records of live allocations to find leaks.
+//!
+//! Once started, every allocation is recorded with a sequence number and
+//! the innermost [`site`] active at the time, until it is freed. Taking a
+//! [`checkpoint`] before some work and dumping the allocations made since
+//! afterwards shows what the work left behind.
+//!
+//! ```ignore
+//! tracking::start();
+//! let checkpoint = tracking::checkpoint();
+//! {
+//!     let _site = tracking::site("pmem");
+//!     pmem::MANAGER.lock().create_pool("a", 4096)?;
+//! }
+//! tracking::dump_since(checkpoint);
+//! ```
+
+use crate::println;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+/// The number of live allocations that can be recorded at once. Further
+/// ones are counted but not recorded.
+pub const CAPACITY: usize = 1024;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACKER: Mutex<Tracker> = Mutex::new(Tracker::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub addr: usize,
+    pub size: usize,
+    pub site: &'static str,
+    pub sequence: u64,
+}
+
+/// The point in the sequence of allocations at which it was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Checkpoint(u64);
+
+struct Tracker {
+    records: [Option<Record>; CAPACITY],
+    next_sequence: u64,
+    /// Allocations that weren't recorded because the records were full.
+    dropped: u64,
+    site: &'static str,
+}
+
+impl Tracker {
+    const fn new() -> Self {
+        Tracker {
+            records: [None; CAPACITY],
+            next_sequence: 0,
+            dropped: 0,
+            site: "unknown",
+        }
+    }
+}
+
+/// Starts recording allocations.
+pub fn start() {
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// Stops recording allocations and forgets the recorded ones.
+pub fn stop() {
+    ENABLED.store(false, Ordering::Release);
+    let mut tracker = TRACKER.lock();
+    tracker.records = [None; CAPACITY];
+    tracker.dropped = 0;
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+pub fn checkpoint() -> Checkpoint {
+    Checkpoint(TRACKER.lock().next_sequence)
+}
+
+/// Attributes allocations to `name` until the returned guard is dropped.
+pub fn site(name: &'static str) -> SiteGuard {
+    let mut tracker = TRACKER.lock();
+    let previous = tracker.site;
+    tracker.site = name;
+    SiteGuard { previous }
+}
+
+pub struct SiteGuard {
+    previous: &'static str,
+}
+
+impl Drop for SiteGuard {
+    fn drop(&mut self) {
+        TRACKER.lock().site = self.previous;
+    }
+}
+
+/// Returns the recorded allocations made since `checkpoint` that are still
+/// live, oldest first.
+pub fn live_since(checkpoint: Checkpoint) -> Vec<Record> {
+    // leaves out the allocation of the result, which is made before the
+    // lock is taken as allocating with it held would deadlock
+    let end = self::checkpoint();
+    let mut live: Vec<Record> = Vec::with_capacity(CAPACITY);
+    let tracker = TRACKER.lock();
+    live.extend(
+        tracker
+            .records
+            .iter()
+            .flatten()
+            .filter(|r| (checkpoint.0..end.0).contains(&r.sequence)),
+    );
+    drop(tracker);
+
+    live.sort_unstable_by_key(|r| r.sequence);
+    live
+}
+
+/// Prints the allocations made since `checkpoint` that are still live.
+pub fn dump_since(checkpoint: Checkpoint) {
+    let live = live_since(checkpoint);
+    let dropped = TRACKER.lock().dropped;
+
+    println!("{} live allocations since #{}:", live.len(), checkpoint.0);
+    for record in &live {
+        println!(
+            "  #{} 0x{:012x} {} bytes from {}",
+            record.sequence, record.addr, record.size, record.site
+        );
+    }
+    if dropped > 0 {
+        println!("{} allocations weren't recorded", dropped);
+    }
+}
+
+pub(super) fn record_alloc(addr: *mut u8, size: usize) {
+    if !is_enabled() || addr.is_null() {
+        return;
+    }
+
+    let mut tracker = TRACKER.lock();
+    let record = Record {
+        addr: addr as usize,
+        size,
+        site: tracker.site,
+        sequence: tracker.next_sequence,
+    };
+    tracker.next_sequence += 1;
+    match tracker.records.iter_mut().find(|r| r.is_none()) {
+        Some(slot) => *slot = Some(record),
+        None => tracker.dropped += 1,
+    }
+}
+
+pub(super) fn record_free(addr: *mut u8) {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut tracker = TRACKER.lock();
+    let slot = tracker
+        .records
+        .iter_mut()
+        .find(|r| r.is_some_and(|r| r.addr == addr as usize));
+    if let Some(slot) = slot {
+        *slot = None;
+    }
+}
@@ -0,0 +1,234 @@
+//! Environment variables that persist across reboots.
+//!
+//! The compiled-in [`DEFAULTS`] apply until they are overridden. Changes are
+//! stored in the pool [`POOL_NAME`] as `NAME=VALUE` lines, and a line with a
+//! bare `NAME` records that a default was unset. Without persistent memory
+//! only the defaults are available.
+
+use crate::pmem::file::PoolFile;
+use crate::pmem::PmemError;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::ffi::CString;
+use alloc::string::String;
+use alloc::vec::Vec;
+use log::warn;
+use spin::Mutex;
+
+mod format;
+
+pub const POOL_NAME: &str = "env";
+const TMP_POOL_NAME: &str = "env.tmp";
+
+pub const DEFAULTS: &[(&str, &str)] = &[("CPUS", "1"), ("VERIFY", "2")];
+
+static ENV: Mutex<Option<BTreeMap<String, CString>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvError {
+    /// The name is empty or contains `=`, a NUL byte or a newline.
+    InvalidName,
+    /// The value contains a NUL byte or a newline.
+    InvalidValue,
+    /// The variables couldn't be written to persistent memory.
+    Pmem(PmemError),
+}
+
+impl From<PmemError> for EnvError {
+    fn from(err: PmemError) -> Self {
+        EnvError::Pmem(err)
+    }
+}
+
+/// Reads the variables from persistent memory again, discarding the ones
+/// loaded before.
+///
+/// Needed once the pmem manager is initialized, as variables looked up
+/// before only fall back to the defaults.
+pub fn reload() {
+    *ENV.lock() = Some(load());
+}
+
+pub fn get(name: &str) -> Option<String> {
+    with_vars(|vars| {
+        vars.get(name)
+            .map(|value| value.to_str().unwrap().to_owned())
+    })
+}
+
+/// Sets a variable and persists all changes.
+pub fn set(name: &str, value: &str) -> Result<(), EnvError> {
+    check_name(name)?;
+    if value.contains(['\0', '\n']) {
+        return Err(EnvError::InvalidValue);
+    }
+
+    update(name, Some(CString::new(value).unwrap()))
+}
+
+/// Removes a variable and persists all changes.
+pub fn remove(name: &str) -> Result<(), EnvError> {
+    check_name(name)?;
+    update(name, None)
+}
+
+/// Returns all variables sorted by name.
+pub fn vars() -> Vec<(String, String)> {
+    with_vars(|vars| {
+        vars.iter()
+            .map(|(name, value)| (name.clone(), value.to_str().unwrap().to_owned()))
+            .collect()
+    })
+}
+
+fn check_name(name: &str) -> Result<(), EnvError> {
+    if format::check_name(name) {
+        Ok(())
+    } else {
+        Err(EnvError::InvalidName)
+    }
+}
+
+fn with_vars<T>(f: impl FnOnce(&mut BTreeMap<String, CString>) -> T) -> T {
+    let mut env = ENV.lock();
+    f(env.get_or_insert_with(load))
+}
+
+/// Sets or removes a variable and writes the change back, undoing it if it
+/// can't be persisted.
+fn update(name: &str, value: Option<CString>) -> Result<(), EnvError> {
+    with_vars(|vars| {
+        let old = match value {
+            Some(value) => vars.insert(name.to_owned(), value),
+            None => vars.remove(name),
+        };
+
+        let res = save(vars);
+        if res.is_err() {
+            match old {
+                Some(old) => vars.insert(name.to_owned(), old),
+                None => vars.remove(name),
+            };
+        }
+        res.map_err(EnvError::from)
+    })
+}
+
+fn defaults() -> BTreeMap<String, CString> {
+    DEFAULTS
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), CString::new(value).unwrap()))
+        .collect()
+}
+
+fn load() -> BTreeMap<String, CString> {
+    let mut vars = defaults();
+
+    let mut content = Vec::new();
+    // the new version is complete once the old one is gone, see `save`. It
+    // takes the old one's place before the next `save` truncates it.
+    let res = match PoolFile::open(POOL_NAME) {
+        Err(PmemError::NotFound) => {
+            let renamed = crate::pmem::MANAGER
+                .lock()
+                .rename_pool(TMP_POOL_NAME, POOL_NAME);
+            renamed.and_then(|()| PoolFile::open(POOL_NAME))
+        }
+        res => res,
+    };
+    let res = res.and_then(|mut file| {
+        content.resize(file.len()? as usize, 0);
+        file.read(&mut content)
+    });
+    match res {
+        Ok(_) => {}
+        Err(PmemError::NotFound) => return vars,
+        Err(err) => {
+            warn!("Couldn't read environment, using defaults: {}", err);
+            return vars;
+        }
+    }
+
+    format::parse(&content, &mut vars);
+    vars
+}
+
+/// Writes the differences to the defaults into a new pool that then
+/// replaces the old one. The replacement destroys the old pool before
+/// renaming the new one, so after a crash in between only the new pool is
+/// left, which `load` puts in the old one's place.
+fn save(vars: &BTreeMap<String, CString>) -> Result<(), PmemError> {
+    let content = format::serialize(vars, &defaults());
+
+    let mut file = PoolFile::create(TMP_POOL_NAME)?;
+    file.write(content.as_bytes())?;
+    file.sync()?;
+    drop(file);
+
+    crate::pmem::MANAGER
+        .lock()
+        .rename_pool(TMP_POOL_NAME, POOL_NAME)
+}
+
+mod ffi {
+    use super::EnvError;
+    use crate::errno;
+    use core::ffi::{c_char, c_int, CStr};
+    use core::ptr;
+
+    /// # Safety
+    ///
+    /// `s` must be null or point to a NUL-terminated string.
+    unsafe fn str_from_ptr<'a>(s: *const c_char) -> Option<&'a str> {
+        (!s.is_null())
+            .then(|| CStr::from_ptr(s).to_str().ok())
+            .flatten()
+    }
+
+    fn result(res: Result<(), EnvError>) -> c_int {
+        match res {
+            Ok(()) => 0,
+            Err(err) => {
+                errno::set(match err {
+                    EnvError::InvalidName | EnvError::InvalidValue => errno::EINVAL,
+                    EnvError::Pmem(err) => err.errno(),
+                });
+                -1
+            }
+        }
+    }
+
+    /// The returned string stays valid until the variable is changed.
+    #[no_mangle]
+    extern "C" fn getenv(name: *const c_char) -> *const c_char {
+        let Some(name) = (unsafe { str_from_ptr(name) }) else {
+            return ptr::null();
+        };
+
+        super::with_vars(|vars| vars.get(name).map_or(ptr::null(), |value| value.as_ptr()))
+    }
+
+    #[no_mangle]
+    extern "C" fn setenv(name: *const c_char, value: *const c_char, overwrite: c_int) -> c_int {
+        let (Some(name), Some(value)) = (unsafe { (str_from_ptr(name), str_from_ptr(value)) })
+        else {
+            errno::set(errno::EINVAL);
+            return -1;
+        };
+
+        if overwrite == 0 && super::get(name).is_some() {
+            return 0;
+        }
+        result(super::set(name, value))
+    }
+
+    #[no_mangle]
+    extern "C" fn unsetenv(name: *const c_char) -> c_int {
+        let Some(name) = (unsafe { str_from_ptr(name) }) else {
+            errno::set(errno::EINVAL);
+            return -1;
+        };
+
+        result(super::remove(name))
+    }
+}
@@ -0,0 +1,52 @@
+//! The `NAME=VALUE` lines the variables are stored as.
+
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::ffi::CString;
+use alloc::string::String;
+use log::warn;
+
+/// Checks that a name can be stored on a line of its own.
+pub fn check_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['=', '\0', '\n'])
+}
+
+/// Applies the stored lines to `vars`, which hold the defaults.
+pub fn parse(content: &[u8], vars: &mut BTreeMap<String, CString>) {
+    for line in String::from_utf8_lossy(content).lines() {
+        match line.split_once('=') {
+            Some((name, value)) if check_name(name) => match CString::new(value) {
+                Ok(value) => {
+                    vars.insert(name.to_owned(), value);
+                }
+                Err(_) => warn!("Ignoring environment entry '{}' with a NUL byte", name),
+            },
+            None if check_name(line) => {
+                vars.remove(line);
+            }
+            _ => warn!("Ignoring malformed environment entry '{}'", line),
+        }
+    }
+}
+
+/// Returns the lines that turn `defaults` into `vars`: the variables that
+/// differ from their default, followed by the bare names of the unset
+/// defaults.
+pub fn serialize(vars: &BTreeMap<String, CString>, defaults: &BTreeMap<String, CString>) -> String {
+    let mut content = String::new();
+
+    for (name, value) in vars.iter() {
+        if defaults.get(name) != Some(value) {
+            content.push_str(name);
+            content.push('=');
+            content.push_str(value.to_str().unwrap());
+            content.push('\n');
+        }
+    }
+    for name in defaults.keys().filter(|name| !vars.contains_key(*name)) {
+        content.push_str(name);
+        content.push('\n');
+    }
+
+    content
+}
@@ -0,0 +1,68 @@
+//! C-style `errno` for the functions the kernel exports to C code.
+//!
+//! The kernel runs on a single CPU without threads, so one global value
+//! serves as the thread-local `errno`.
+
+use core::ffi::c_int;
+use core::sync::atomic::{AtomicI32, Ordering};
+
+pub const EPERM: c_int = 1;
+pub const ENOENT: c_int = 2;
+pub const EIO: c_int = 5;
+pub const EBADF: c_int = 9;
+pub const ENOMEM: c_int = 12;
+pub const EEXIST: c_int = 17;
+pub const EINVAL: c_int = 22;
+pub const ENFILE: c_int = 23;
+pub const EMFILE: c_int = 24;
+pub const EFBIG: c_int = 27;
+pub const ENOSPC: c_int = 28;
+pub const ESPIPE: c_int = 29;
+pub const ERANGE: c_int = 34;
+pub const ENAMETOOLONG: c_int = 36;
+
+static ERRNO: AtomicI32 = AtomicI32::new(0);
+
+pub fn get() -> c_int {
+    ERRNO.load(Ordering::Relaxed)
+}
+
+pub fn set(errno: c_int) {
+    ERRNO.store(errno, Ordering::Relaxed);
+}
+
+/// Returns the message for an error number, terminated by a NUL byte.
+pub fn message(errno: c_int) -> &'static str {
+    match errno {
+        0 => "Success\0",
+        EPERM => "Operation not permitted\0",
+        ENOENT => "No such file or directory\0",
+        EIO => "Input/output error\0",
+        EBADF => "Bad file descriptor\0",
+        ENOMEM => "Cannot allocate memory\0",
+        EEXIST => "File exists\0",
+        EINVAL => "Invalid argument\0",
+        ENFILE => "Too many open files in system\0",
+        EMFILE => "Too many open files\0",
+        EFBIG => "File too large\0",
+        ENOSPC => "No space left on device\0",
+        ESPIPE => "Illegal seek\0",
+        ERANGE => "Numerical result out of range\0",
+        ENAMETOOLONG => "File name too long\0",
+        _ => "Unknown error\0",
+    }
+}
+
+mod ffi {
+    use core::ffi::{c_char, c_int};
+
+    #[no_mangle]
+    extern "C" fn __errno_location() -> *mut c_int {
+        super::ERRNO.as_ptr()
+    }
+
+    #[no_mangle]
+    extern "C" fn strerror(errnum: c_int) -> *const c_char {
+        super::message(errnum).as_ptr() as *const c_char
+    }
+}
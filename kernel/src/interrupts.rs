@@ -1,4 +1,5 @@
-use crate::{gdt, hlt_loop, println};
+use crate::allocator::{self, HeapFault};
+use crate::{gdt, hlt_loop, println, vmem};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
@@ -57,9 +58,30 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    let addr = Cr2::read();
+
+    let heap_fault = match allocator::handle_page_fault(addr, error_code) {
+        Ok(()) => return,
+        Err(err) => err,
+    };
+
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Accessed Address: {:?}", addr);
     println!("Error Code: {:?}", error_code);
+    if !matches!(heap_fault, HeapFault::OutsideHeap) {
+        println!("Heap: {:?}", heap_fault);
+    }
+
+    // the fault might have happened while the manager was locked
+    if let Some(manager) = vmem::MANAGER.try_lock() {
+        match manager.get().and_then(|m| m.allocation(addr)) {
+            Some(allocation) if allocation.is_guard(addr.as_u64()) => {
+                println!("Region: guard page of {}", allocation)
+            }
+            Some(allocation) => println!("Region: {}", allocation),
+            None => println!("Region: unknown"),
+        }
+    }
     println!("{:#?}", stack_frame);
     hlt_loop();
 }
@@ -72,6 +94,8 @@ extern "x86-interrupt" fn double_fault_handler(
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::task::timer::tick();
+
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
@@ -0,0 +1,255 @@
+//! A map of physical memory in the style of `/proc/iomem`.
+//!
+//! The map combines the bootloader's memory regions with what the kernel
+//! knows about the memory itself: the frame allocator, the frames backing
+//! the heap, ACPI tables, NVDIMMs with their pools, the framebuffer and
+//! MMIO. Resources may nest, like a pool inside its NVDIMM, but any other
+//! overlap means two parts of the kernel disagree about a piece of memory
+//! and is reported as a conflict.
+
+use crate::acpi::{sdt::Signature, AcpiHandler, AcpiTables};
+use crate::allocator::{HEAP_SIZE, HEAP_START};
+use crate::memory::FRAMES;
+use crate::pmem::{self, table};
+use crate::vmem::{self, MappedRegions, Owner};
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+use core::fmt;
+use core::ops::Range;
+use x86_64::structures::paging::PageSize;
+use x86_64::{PhysAddr, VirtAddr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// RAM the bootloader left to the kernel.
+    Usable,
+    /// Memory the bootloader or the firmware keeps for itself.
+    Reserved(MemoryRegionKind),
+    AcpiTable(Signature),
+    /// The system physical address range of the NVDIMM with this handle.
+    Nvdimm(u32),
+    PoolTable,
+    Pool(String),
+    Framebuffer,
+    Mmio,
+    /// The frames the frame allocator keeps its bitmap in.
+    FrameBitmap,
+    FreeFrames,
+    Heap,
+}
+
+impl Kind {
+    /// Returns whether a resource of this kind may contain one of `other`.
+    fn may_contain(&self, other: &Kind) -> bool {
+        use Kind::*;
+        matches!(
+            (self, other),
+            (Usable, FrameBitmap | FreeFrames | Heap)
+                | (Reserved(_), AcpiTable(_) | Nvdimm(_) | Framebuffer | Mmio)
+                | (Nvdimm(_), PoolTable | Pool(_))
+        )
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usable => f.write_str("usable RAM"),
+            Self::Reserved(kind) => write!(f, "reserved ({:?})", kind),
+            Self::AcpiTable(signature) => write!(f, "ACPI table {:?}", signature),
+            Self::Nvdimm(handle) => write!(f, "NVDIMM {:x}", handle),
+            Self::PoolTable => f.write_str("pool table"),
+            Self::Pool(name) => write!(f, "pool '{}'", name),
+            Self::Framebuffer => f.write_str("framebuffer"),
+            Self::Mmio => f.write_str("MMIO"),
+            Self::FrameBitmap => f.write_str("frame bitmap"),
+            Self::FreeFrames => f.write_str("free frames"),
+            Self::Heap => f.write_str("kernel heap"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub range: Range<u64>,
+    pub kind: Kind,
+}
+
+impl Resource {
+    fn contains(&self, other: &Resource) -> bool {
+        self.range.start <= other.range.start && other.range.end <= self.range.end
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:012x}-{:012x} : {}",
+            self.range.start,
+            self.range.end - 1,
+            self.kind
+        )
+    }
+}
+
+/// Physical memory resources ordered by address, enclosing ones first.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMap {
+    resources: Vec<Resource>,
+}
+
+impl MemoryMap {
+    pub const fn new() -> Self {
+        MemoryMap {
+            resources: Vec::new(),
+        }
+    }
+
+    /// Adds a resource. Empty ranges are skipped.
+    pub fn insert(&mut self, range: Range<u64>, kind: Kind) {
+        if range.is_empty() {
+            return;
+        }
+        let resource = Resource { range, kind };
+        let index = self.resources.partition_point(|r| {
+            (r.range.start, !r.range.end) <= (resource.range.start, !resource.range.end)
+        });
+        self.resources.insert(index, resource);
+    }
+
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    /// Returns the pairs of resources that overlap without one containing
+    /// the other, and the resources whose innermost enclosing resource may
+    /// not contain them.
+    pub fn conflicts(&self) -> Vec<(&Resource, &Resource)> {
+        let mut conflicts = Vec::new();
+        let mut parents: Vec<&Resource> = Vec::new();
+        for (i, a) in self.resources.iter().enumerate() {
+            while parents.last().is_some_and(|p| !p.contains(a)) {
+                parents.pop();
+            }
+            // the outer resources were checked against their own parents
+            if let Some(parent) = parents.last() {
+                if !parent.kind.may_contain(&a.kind) {
+                    conflicts.push((*parent, a));
+                }
+            }
+            parents.push(a);
+
+            let overlapping = self.resources[i + 1..]
+                .iter()
+                .take_while(|b| b.range.start < a.range.end);
+            for b in overlapping {
+                if !a.contains(b) && !b.contains(a) {
+                    conflicts.push((a, b));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+impl fmt::Display for MemoryMap {
+    /// Prints one resource per line, indented below the resources that
+    /// contain it, followed by the conflicts.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parents: Vec<&Resource> = Vec::new();
+        for resource in &self.resources {
+            while parents.last().is_some_and(|p| !p.contains(resource)) {
+                parents.pop();
+            }
+            writeln!(f, "{:1$}{2}", "", parents.len() * 2, resource)?;
+            parents.push(resource);
+        }
+
+        for (a, b) in self.conflicts() {
+            writeln!(f, "error: {} overlaps {}", a, b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the map from the bootloader's regions, the ACPI tables and the
+/// state of the frame allocator and the memory managers.
+pub fn collect<H: AcpiHandler>(
+    memory_regions: &[MemoryRegion],
+    acpi_tables: Option<&AcpiTables<H>>,
+) -> MemoryMap {
+    let mut map = MemoryMap::new();
+
+    for region in memory_regions {
+        let kind = match region.kind {
+            MemoryRegionKind::Usable => Kind::Usable,
+            kind => Kind::Reserved(kind),
+        };
+        map.insert(region.start..region.end, kind);
+    }
+
+    for (&signature, sdt) in acpi_tables.iter().flat_map(|tables| tables.sdts.iter()) {
+        let start = sdt.physical_address as u64;
+        map.insert(
+            start..(start + sdt.length as u64),
+            Kind::AcpiTable(signature),
+        );
+    }
+
+    // growing the map may fault in heap pages, which needs the frame
+    // allocator unlocked
+    let bitmap = FRAMES.lock().bitmap_range();
+    map.insert(bitmap, Kind::FrameBitmap);
+    let mut addr = PhysAddr::new(0);
+    loop {
+        let Some(free) = FRAMES.lock().next_free_range(addr) else {
+            break;
+        };
+        addr = free.end.start_address();
+        map.insert(
+            free.start.start_address().as_u64()..addr.as_u64(),
+            Kind::FreeFrames,
+        );
+    }
+
+    if let Some(manager) = vmem::MANAGER.lock().get_mut() {
+        let mut virtual_ranges: Vec<_> = manager
+            .allocations()
+            .filter_map(|allocation| match allocation.owner {
+                Owner::Framebuffer => Some((allocation.mapped.clone(), Kind::Framebuffer)),
+                Owner::Mmio => Some((allocation.mapped.clone(), Kind::Mmio)),
+                _ => None,
+            })
+            .collect();
+        virtual_ranges.push((
+            HEAP_START as u64..(HEAP_START + HEAP_SIZE) as u64,
+            Kind::Heap,
+        ));
+
+        let walker = manager.walker();
+        for (range, kind) in virtual_ranges {
+            let range = VirtAddr::new(range.start)..VirtAddr::new(range.end);
+            for region in walker.mappings(range).into_regions() {
+                map.insert(region.phys, kind.clone());
+            }
+        }
+    }
+
+    for (device, pools) in pmem::MANAGER.lock().devices() {
+        let start = device.phys_addr.as_u64();
+        map.insert(start..(start + device.size), Kind::Nvdimm(device.handle));
+        map.insert(start..(start + table::PageSize::SIZE), Kind::PoolTable);
+        for entry in pools.entries() {
+            let pool = start + entry.offset();
+            map.insert(
+                pool..(pool + entry.real_len()),
+                Kind::Pool(entry.name().to_owned()),
+            );
+        }
+    }
+
+    map
+}
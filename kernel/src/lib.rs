@@ -2,6 +2,8 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 #![feature(const_mut_refs)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
@@ -11,9 +13,12 @@ use core::panic::PanicInfo;
 
 pub mod acpi;
 pub mod allocator;
+pub mod env;
+pub mod errno;
 pub mod framebuffer;
 pub mod gdt;
 pub mod interrupts;
+pub mod iomem;
 pub mod logger;
 pub mod memory;
 pub mod nfit;
@@ -25,7 +30,9 @@ pub mod vmem;
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
+    unsafe { vmem::init_attributes() };
     unsafe { interrupts::PICS.lock().initialize() };
+    task::timer::init();
     x86_64::instructions::interrupts::enable();
 }
 pub trait Testable {
@@ -99,8 +99,10 @@ mod ffi {
 
     #[no_mangle]
     fn perror(s: *const c_char) {
-        if let Ok(s) = unsafe { CStr::from_ptr(s) }.to_str() {
-            eprintln!("{}", s);
+        let msg = crate::errno::message(crate::errno::get()).trim_end_matches('\0');
+        match (!s.is_null()).then(|| unsafe { CStr::from_ptr(s) }.to_str()) {
+            Some(Ok(s)) if !s.is_empty() => eprintln!("{}: {}", s, msg),
+            _ => eprintln!("{}", msg),
         }
     }
 }
@@ -13,12 +13,15 @@ use kernel::{logger, println};
 
 mod corundum_bench;
 mod corundum_test;
-use bootloader_api::info::MemoryRegionKind;
-use core::ops::DerefMut;
-use kernel::acpi::{self, sdt, AcpiError};
+use alloc::vec::Vec;
+use bootloader_api::info::MemoryRegion;
+use core::time::Duration;
+use kernel::acpi::{self, sdt};
+use kernel::iomem;
 use kernel::nfit;
 use kernel::pmem;
 use kernel::task::keyboard::ScancodeStream;
+use kernel::task::timer;
 use kernel::vmem::{self, MappedRegions, UsableRegions};
 use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
 use x86_64::VirtAddr;
@@ -36,7 +39,12 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     use kernel::allocator;
     use kernel::memory;
 
-    logger::init(boot_info.framebuffer.as_mut().expect("no framebuffer"));
+    let framebuffer = boot_info.framebuffer.as_mut().expect("no framebuffer");
+    let framebuffer_range = {
+        let start = VirtAddr::from_ptr(framebuffer.buffer().as_ptr());
+        start..(start + framebuffer.buffer().len())
+    };
+    logger::init(framebuffer);
     log::set_max_level(log::LevelFilter::Trace);
     println!("Hello World{}", "!");
     kernel::init();
@@ -45,33 +53,14 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
 
     unsafe {
-        memory::FRAMES.lock().init(
-            boot_info
-                .memory_regions
-                .iter()
-                .filter(|r| r.kind == MemoryRegionKind::Usable)
-                .max_by(|a, b| (a.end - a.start).cmp(&(b.end - b.start)))
-                .copied()
-                .unwrap(),
-        );
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
     }
 
-    allocator::init_heap(&mut mapper, memory::FRAMES.lock().deref_mut())
-        .expect("heap initialization failed");
+    unsafe { allocator::init_heap(phys_mem_offset) };
 
-    p!("===========================");
-    p!("Physical Memory Information");
-    p!("===========================");
-
-    p!("Phys memory regions passed by bootloader:");
-    for region in boot_info.memory_regions.iter() {
-        p!(
-            "0x{:012x}-0x{:012x} - {:?}-Region",
-            region.start,
-            region.end - 1,
-            region.kind,
-        );
-    }
+    let memory_regions = boot_info.memory_regions.to_vec();
 
     p!("==========================");
     p!("Virtual Memory Information");
@@ -82,11 +71,12 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let non_usable = mappings.into_regions();
     non_usable.iter().for_each(|region| {
         p!(
-            "0x{:012x}-0x{:012x} (size: 0x{:012x}, phys: 0x{:012x})",
+            "0x{:012x}-0x{:012x} (size: 0x{:012x}, phys: 0x{:012x}, flags: {:?})",
             region.virt.start,
             region.virt.end - 1,
             region.virt.end - region.virt.start - 1,
             region.phys.start,
+            region.flags,
         )
     });
 
@@ -102,6 +92,18 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     });
 
     let mut page_allocator = vmem::Manager::new(mapper, &memory::FRAMES, usable);
+    page_allocator
+        .set_attributes(
+            framebuffer_range.clone(),
+            vmem::Attributes::new().cache(vmem::CacheType::WriteCombining),
+        )
+        .expect("failed to map framebuffer write-combining");
+    page_allocator.register(framebuffer_range, vmem::Owner::Framebuffer);
+    let heap_start = VirtAddr::new(allocator::HEAP_START as u64);
+    page_allocator.register(
+        heap_start..(heap_start + allocator::HEAP_SIZE),
+        vmem::Owner::Heap,
+    );
     p!(
         "PML4(CR3) is at 0x{:012x} (phys: 0x{:012x})",
         page_allocator.virtual_address(),
@@ -116,6 +118,7 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     executor.spawn(Task::new(pmem_stuff(
         boot_info.rsdp_addr.into_option(),
         phys_mem_offset,
+        memory_regions,
     )));
     executor.run();
 }
@@ -140,7 +143,11 @@ fn panic(info: &PanicInfo) -> ! {
     kernel::test_panic_handler(info)
 }
 
-async fn pmem_stuff(rsdp: Option<u64>, phys_mem_offset: VirtAddr) {
+async fn pmem_stuff(
+    rsdp: Option<u64>,
+    phys_mem_offset: VirtAddr,
+    memory_regions: Vec<MemoryRegion>,
+) {
     let mut scancodes = ScancodeStream::new();
     let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
 
@@ -152,15 +159,12 @@ async fn pmem_stuff(rsdp: Option<u64>, phys_mem_offset: VirtAddr) {
 
     let acpi_tables = acpi::get_tables(rsdp.expect("no rsdp set"), phys_mem_offset);
 
-    let nfit = unsafe {
-        acpi_tables
-            .get_sdt::<nfit::Nfit>(sdt::Signature::NFIT)
-            .unwrap()
-            .ok_or(AcpiError::TableMissing(sdt::Signature::NFIT))
-            .unwrap()
-    };
+    let nfit = unsafe { acpi_tables.get_sdt::<nfit::Nfit>(sdt::Signature::NFIT) }.unwrap();
+    if nfit.is_none() {
+        p!("No NFIT, looking for persistent memory in the memory map");
+    }
 
-    for (i, e) in nfit.entries().enumerate() {
+    for (i, e) in nfit.iter().flat_map(|nfit| nfit.entries()).enumerate() {
         use nfit::NfitEntry as E;
         match e {
             E::SpaRange(e) => p!("{}. NFIT Entry: {:#?}", i + 1, e),
@@ -172,7 +176,7 @@ async fn pmem_stuff(rsdp: Option<u64>, phys_mem_offset: VirtAddr) {
             E::FlushHintAddress(e) => p!("{}. NFIT Entry: {:#?}", i + 1, e),
             E::PlatformCapabilities(e) => p!("{}. NFIT Entry: {:#?}", i + 1, e),
         }
-        keyboard::getchar(&mut scancodes, &mut keyboard).await;
+        timer::sleep(Duration::from_millis(500)).await;
     }
 
     p!("==============");
@@ -180,8 +184,18 @@ async fn pmem_stuff(rsdp: Option<u64>, phys_mem_offset: VirtAddr) {
     p!("==============");
 
     unsafe {
-        pmem::MANAGER.lock().init(&nfit);
+        pmem::MANAGER.lock().init(nfit.as_deref(), &memory_regions);
     }
+    kernel::env::reload();
+    if let Some(limit) = kernel::env::get("HEAP_COMMIT_LIMIT").and_then(|v| v.parse().ok()) {
+        kernel::allocator::set_commit_limit(limit);
+    }
+
+    p!("===================");
+    p!("Physical Memory Map");
+    p!("===================");
+
+    p!("{}", iomem::collect(&memory_regions, Some(&acpi_tables)));
 
     #[cfg(test)]
     test_main();
@@ -0,0 +1,224 @@
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+use core::ops::Range;
+use core::slice;
+use log::warn;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+
+/// Hands out frames from all usable memory regions, one bit per frame.
+///
+/// The bitmap covers physical memory up to the end of the last usable region
+/// and lives in the first usable region large enough for it. Single frames
+/// are found from a hint to the first word with a free frame, larger and
+/// contiguous allocations search for an aligned run of free frames.
+#[derive(Debug)]
+pub struct BitmapFrameAllocator {
+    /// A set bit marks a free frame.
+    bitmap: &'static mut [u64],
+    /// The first frame holding the bitmap.
+    bitmap_start: usize,
+    /// Index of the first word that may have a free frame.
+    next: usize,
+    free: u64,
+}
+
+impl BitmapFrameAllocator {
+    pub const fn new() -> Self {
+        BitmapFrameAllocator {
+            bitmap: &mut [],
+            bitmap_start: 0,
+            next: 0,
+            free: 0,
+        }
+    }
+
+    /// Frees the frames of all usable regions, except those taken by the
+    /// bitmap and frame 0, so that a zero address never refers to a frame.
+    ///
+    /// # Safety
+    ///
+    /// The usable regions must be unused and the complete physical memory
+    /// must be mapped at `physical_memory_offset`.
+    pub unsafe fn init(&mut self, regions: &[MemoryRegion], physical_memory_offset: VirtAddr) {
+        let usable = || {
+            regions
+                .iter()
+                .filter(|r| r.kind == MemoryRegionKind::Usable)
+                .map(|r| {
+                    let start = x86_64::align_up(r.start, FRAME_SIZE) / FRAME_SIZE;
+                    let end = x86_64::align_down(r.end, FRAME_SIZE) / FRAME_SIZE;
+                    start as usize..end as usize
+                })
+                .filter(|r| !r.is_empty())
+        };
+
+        let frames = usable().map(|r| r.end).max().unwrap_or(0);
+        let words = (frames + 63) / 64;
+        let bitmap_frames = (words * 8 + FRAME_SIZE as usize - 1) / FRAME_SIZE as usize;
+        let bitmap_start = usable()
+            .find(|r| r.len() >= bitmap_frames)
+            .expect("no usable region can hold the frame bitmap")
+            .start;
+
+        let ptr = physical_memory_offset + bitmap_start as u64 * FRAME_SIZE;
+        self.bitmap = slice::from_raw_parts_mut(ptr.as_mut_ptr(), words);
+        self.bitmap.fill(0);
+        self.bitmap_start = bitmap_start;
+        self.next = 0;
+        self.free = 0;
+
+        for region in usable() {
+            self.mark(region.clone(), true);
+            self.free += region.len() as u64;
+        }
+        for taken in [bitmap_start..(bitmap_start + bitmap_frames), 0..1] {
+            let used = taken.clone().filter(|&frame| self.is_free(frame)).count();
+            self.mark(taken, false);
+            self.free -= used as u64;
+        }
+    }
+
+    /// Returns the number of free 4 KiB frames.
+    pub fn free_frames(&self) -> u64 {
+        self.free
+    }
+
+    /// Returns the physical memory holding the bitmap.
+    pub fn bitmap_range(&self) -> Range<u64> {
+        let start = self.bitmap_start as u64 * FRAME_SIZE;
+        start..(start + x86_64::align_up(self.bitmap.len() as u64 * 8, FRAME_SIZE))
+    }
+
+    /// Returns the run of free frames containing `addr` or the first one
+    /// after it.
+    pub fn next_free_range(&self, addr: PhysAddr) -> Option<PhysFrameRange> {
+        let len = self.bitmap.len() * 64;
+        let start = self.find(((addr.as_u64() / FRAME_SIZE) as usize).min(len)..len, true)?;
+        let end = self.find(start..len, false).unwrap_or(len);
+        Some(PhysFrame::range(frame(start), frame(end)))
+    }
+
+    /// Allocates `count` physically contiguous 4 KiB frames, starting at a
+    /// multiple of `alignment`, which must be a power of two.
+    pub fn allocate_contiguous(&mut self, count: u64, alignment: u64) -> Option<PhysFrameRange> {
+        assert!(count > 0, "count must be non-zero");
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
+        let count = count as usize;
+        let align = (alignment / FRAME_SIZE).max(1) as usize;
+        let len = self.bitmap.len() * 64;
+
+        let mut start = align_up(self.next * 64, align);
+        while start + count <= len {
+            match self.find(start..(start + count), false) {
+                Some(used) => start = align_up(used + 1, align),
+                None => {
+                    self.mark(start..(start + count), false);
+                    self.free -= count as u64;
+                    while self.next < self.bitmap.len() && self.bitmap[self.next] == 0 {
+                        self.next += 1;
+                    }
+                    return Some(PhysFrame::range(frame(start), frame(start + count)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Gives back frames returned by [`allocate_contiguous`]. Frames outside
+    /// the bitmap and frames that are free already are ignored, so that they
+    /// don't skew the free count.
+    ///
+    /// # Safety
+    ///
+    /// The frames must be unused.
+    ///
+    /// [`allocate_contiguous`]: Self::allocate_contiguous
+    pub unsafe fn deallocate_contiguous(&mut self, frames: PhysFrameRange) {
+        let start = (frames.start.start_address().as_u64() / FRAME_SIZE) as usize;
+        let end = (frames.end.start_address().as_u64() / FRAME_SIZE) as usize;
+        if end > self.bitmap.len() * 64 {
+            warn!("frames in {:?} were never allocated", frames);
+            return;
+        }
+        if (start..end).any(|frame| self.is_free(frame)) {
+            warn!("frames in {:?} freed twice", frames);
+            return;
+        }
+
+        self.mark(start..end, true);
+        self.free += (end - start) as u64;
+        self.next = self.next.min(start / 64);
+    }
+
+    fn is_free(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    /// Returns the first frame in `frames` that is free or in use.
+    fn find(&self, frames: Range<usize>, free: bool) -> Option<usize> {
+        let mut frame = frames.start;
+        while frame < frames.end {
+            let word = frame / 64;
+            let mask = bits(frame % 64, (frames.end - word * 64).min(64));
+            let found = if free {
+                self.bitmap[word]
+            } else {
+                !self.bitmap[word]
+            } & mask;
+            if found != 0 {
+                return Some(word * 64 + found.trailing_zeros() as usize);
+            }
+            frame = (word + 1) * 64;
+        }
+        None
+    }
+
+    fn mark(&mut self, frames: Range<usize>, free: bool) {
+        let mut frame = frames.start;
+        while frame < frames.end {
+            let word = frame / 64;
+            let mask = bits(frame % 64, (frames.end - word * 64).min(64));
+            if free {
+                self.bitmap[word] |= mask;
+            } else {
+                self.bitmap[word] &= !mask;
+            }
+            frame = (word + 1) * 64;
+        }
+    }
+}
+
+/// Returns a mask with bits `start..end` set.
+fn bits(start: usize, end: usize) -> u64 {
+    let below_end = if end == 64 { !0 } else { (1 << end) - 1 };
+    below_end & !((1 << start) - 1)
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn frame(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
+
+unsafe impl<S: PageSize> FrameAllocator<S> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        let frames = self.allocate_contiguous(S::SIZE / FRAME_SIZE, S::SIZE)?;
+        Some(PhysFrame::containing_address(frames.start.start_address()))
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        let first = PhysFrame::containing_address(frame.start_address());
+        self.deallocate_contiguous(PhysFrame::range(first, first + S::SIZE / FRAME_SIZE));
+    }
+}
@@ -1,146 +1,272 @@
 mod device;
+mod error;
 
 pub use device::*;
+pub use error::*;
+pub mod fd;
 pub mod ffi;
+pub mod file;
+pub mod heap;
 pub mod table;
 
+use crate::allocator::tracking;
 use crate::nfit::Nfit;
 use crate::pmem::table::Table;
-use crate::vmem::{self};
+use crate::vmem::{self, AddressSpace, Attributes, CacheType, Owner};
 use alloc::alloc::{alloc, dealloc, Layout};
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader_api::info::MemoryRegion;
+use core::arch::asm;
+use core::arch::x86_64::_mm_sfence;
 use core::mem::MaybeUninit;
+use core::ptr;
 use core::slice;
+use core::sync::atomic::{AtomicU64, Ordering};
 use corundum::ll;
-use log::trace;
+use log::{trace, warn};
 use spin::Mutex;
-use x86_64::structures::paging::{page::PageRange, Page, PageSize};
-use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    page::PageRange, FrameAllocator, FrameDeallocator, Page, PageSize, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
 
 pub static MANAGER: Mutex<Manager> = Mutex::new(Manager::new());
 
 const USE_HEAP_INSTEAD_OF_PMEM: bool = false;
 
+/// Bumped whenever a pool is resized, moved or destroyed, so that cached
+/// mappings can be validated without taking the manager's lock.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// Identifies a pool independent of its name, so it stays valid across
+/// renames but not across the pool being destroyed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PoolId {
+    handle: u32,
+    index: usize,
+    incarnation: u64,
+}
+
+/// The device handle of a mapped pool, its pages, and if they are writable.
+type Translation = (u32, PageRange<table::PageSize>, bool);
+
+/// Keeps track of the NVDIMMs and the pools on them.
+///
+/// The manager is usually locked with interrupts disabled, where running out
+/// of memory must not panic. Its own allocations are made with `try_reserve`
+/// and fail with [`PmemError::OutOfMemory`].
 pub struct Manager {
     pmems: Vec<ManagedPmem>,
     // FIXME: Put handle into key not value, two pools on different dimms might have the same offset
-    translated: BTreeMap<u64, (u32, PageRange<table::PageSize>)>,
+    translated: Vec<(u64, Translation)>,
+    /// How often a table entry has been freed, so ids of destroyed pools
+    /// don't resolve to a later pool in the same entry.
+    incarnations: Vec<((u32, usize), u64)>,
 }
 
 pub struct ManagedPmem {
     info: NfitDevice,
     pools: Table,
+    /// Uncached mapping of one of the device's flush hint addresses.
+    flush_hint: Option<VirtAddr>,
 }
 
 impl Manager {
     pub const fn new() -> Self {
         Manager {
             pmems: Vec::new(),
-            translated: BTreeMap::new(),
+            translated: Vec::new(),
+            incarnations: Vec::new(),
         }
     }
 
+    /// Takes the devices from the NFIT and the persistent memory regions of
+    /// the memory map it doesn't describe.
+    ///
     /// # Safety
     ///
     /// Maps the persistent memory's frames and creates mutable references to it.
     /// This function must not be called more than once.
-    pub unsafe fn init(&mut self, nfit: &Nfit) {
+    pub unsafe fn init(&mut self, nfit: Option<&Nfit>, memory_regions: &[MemoryRegion]) {
         let mut locked = vmem::MANAGER.lock();
         let page_allocator = locked.get_mut().unwrap();
 
-        for device in get_devices(nfit).iter() {
+        let mut devices = nfit.map(get_devices).unwrap_or_default();
+        let legacy = get_legacy_devices(memory_regions, &devices);
+        for device in legacy.iter() {
+            warn!(
+                "Using persistent memory at 0x{:012x} without NFIT, assuming caches must be written back",
+                device.phys_addr.as_u64(),
+            );
+        }
+        devices.extend(legacy);
+        if self.pmems.try_reserve_exact(devices.len()).is_err() {
+            warn!("Out of memory for {} nvdimms", devices.len());
+            return;
+        }
+
+        for device in devices {
             trace!("Found nvdimm {:#?}", device);
 
             let mapped = page_allocator
-                .allocate::<table::PageSize>(device.phys_addr, 1)
+                .allocate::<table::PageSize>(
+                    device.phys_addr,
+                    1,
+                    Attributes::new(),
+                    Owner::PoolTable,
+                )
                 .unwrap();
+            let pools = match Table::new(&device, mapped) {
+                Ok(pools) => pools,
+                Err(err) => {
+                    warn!("Skipping nvdimm {:#x}: {}", device.handle, err);
+                    page_allocator.deallocate(mapped);
+                    continue;
+                }
+            };
+
+            let flush_hint = device
+                .flush_addresses
+                .as_ref()
+                .and_then(|addrs| addrs.first())
+                .and_then(|&addr| {
+                    let offset = addr.as_u64() % table::PageSize::SIZE;
+                    page_allocator
+                        .allocate::<table::PageSize>(
+                            addr.align_down(table::PageSize::SIZE),
+                            1,
+                            Attributes::new().cache(CacheType::Uncacheable),
+                            Owner::Mmio,
+                        )
+                        .map(|pages| pages.start.start_address() + offset)
+                });
 
             self.pmems.push(ManagedPmem {
-                info: device.clone(),
-                pools: Table::new(device, mapped),
+                info: device,
+                pools,
+                flush_hint,
             });
         }
     }
 
-    pub fn create_pool(&mut self, name: &str, size: u64) -> Option<(u64, u64)> {
-        if self.get_pool(name).is_some() {
-            return None;
+    pub fn create_pool(&mut self, name: &str, size: u64) -> Result<(u64, u64), PmemError> {
+        table::check_name(name)?;
+        if self.find_pool(name).is_ok() {
+            return Err(PmemError::AlreadyExists);
         }
 
-        self.pmems
-            .iter_mut()
-            .find_map(|pmem| pmem.pools.allocate(name, size))
-            .map(|_| self.get_pool(name).unwrap())
+        // try every nvdimm in turn and report why the last one failed
+        let mut res = Err(PmemError::NoSpace);
+        for pmem in self.pmems.iter_mut() {
+            res = pmem.pools.allocate(name, size);
+            if res.is_ok() {
+                break;
+            }
+        }
+
+        res.and_then(|_| self.get_pool(name, true))
     }
 
-    pub fn get_pool(&mut self, name: &str) -> Option<(u64, u64)> {
-        self.ensure_pool_is_mapped_if_existent(name)
-            .and_then(|(handle, index)| {
-                let pmem = self.pmems.iter().find(|p| p.info.handle == handle)?;
-                let entry = pmem.pools.get(index)?;
+    pub fn get_pool(&mut self, name: &str, writable: bool) -> Result<(u64, u64), PmemError> {
+        let id = self.find_pool(name)?;
+        self.get_pool_by_id(id, writable)
+    }
 
-                self.translated
-                    .get(&entry.offset())
-                    .map(|(_, r)| r.start.start_address().as_u64())
-                    .map(|addr| (addr, entry.len()))
-            })
+    /// Maps a pool, read-only unless `writable` is set, and returns its
+    /// address and length.
+    ///
+    /// A pool is mapped only once, so it stays writable after any caller
+    /// asked for that, until it is moved or destroyed.
+    pub fn get_pool_by_id(&mut self, id: PoolId, writable: bool) -> Result<(u64, u64), PmemError> {
+        self.ensure_pool_is_mapped(id, writable)?;
+        let entry = self
+            .pmem(id.handle)?
+            .pools
+            .get(id.index)
+            .ok_or(PmemError::NotFound)?;
+
+        self.translation(entry.offset())
+            .map(|(_, r, _)| r.start.start_address().as_u64())
+            .map(|addr| (addr, entry.len()))
+            .ok_or(PmemError::NotFound)
     }
 
-    pub fn destroy_pool(&mut self, name: &str) -> bool {
-        let Some((handle, entry)) = self.pmems.iter().find_map(|pmem| {
-            pmem.pools
-                .entries()
-                .into_iter()
-                .find(|entry| entry.name() == name)
-                .map(|entry| (pmem.info.handle, entry))
-        }) else {
-            return false;
-        };
-        let index = entry.index();
-        let offset = entry.offset();
-        let real_len = entry.real_len();
+    /// Maps a pool into `space` alone, so it doesn't appear in other address
+    /// spaces. The mapping is left as is when the pool is resized or
+    /// destroyed, so it must be deallocated before.
+    pub fn map_pool_into<A>(
+        &self,
+        id: PoolId,
+        writable: bool,
+        space: &mut AddressSpace<'_, A>,
+    ) -> Result<PageRange<table::PageSize>, PmemError>
+    where
+        A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+    {
+        if id.incarnation != self.incarnation(id.handle, id.index) {
+            return Err(PmemError::NotFound);
+        }
+        let pmem = self.pmem(id.handle)?;
+        let entry = pmem.pools.get(id.index).ok_or(PmemError::NotFound)?;
 
-        if self
-            .pmems
+        space
+            .allocate_guarded::<table::PageSize>(
+                pmem.info.phys_addr + entry.offset(),
+                entry.frames(),
+                Attributes::new().writable(writable),
+                Owner::Pool(to_owned(entry.name())?),
+            )
+            .ok_or(PmemError::OutOfVirtualMemory)
+    }
+
+    pub fn destroy_pool(&mut self, name: &str) -> Result<(), PmemError> {
+        let PoolId { handle, index, .. } = self.find_pool(name)?;
+        let offset = self
+            .pmem(handle)?
+            .pools
+            .get(index)
+            .ok_or(PmemError::NotFound)?
+            .offset();
+        // reserved up front, so that the pool isn't left half destroyed
+        let _site = tracking::site("pmem");
+        self.incarnations.try_reserve(1)?;
+
+        self.pmem_mut(handle)?.pools.deallocate(index)?;
+        match self
+            .incarnations
             .iter_mut()
-            .find(|pmem| pmem.info.handle == handle)
-            .map(|pmem| pmem.pools.deallocate(index))
-            .unwrap_or(false)
+            .find(|(key, _)| *key == (handle, index))
         {
-            if let Some((_, r)) = self.translated.get(&offset) {
-                if !USE_HEAP_INSTEAD_OF_PMEM
-                    && vmem::MANAGER
-                        .lock()
-                        .get_mut()
-                        .unwrap()
-                        .deallocate::<table::PageSize>(*r)
-                    || USE_HEAP_INSTEAD_OF_PMEM && {
-                        unsafe {
-                            dealloc(
-                                r.start.start_address().as_u64() as *mut u8,
-                                Layout::from_size_align(
-                                    real_len as usize,
-                                    table::PageSize::SIZE as usize,
-                                )
-                                .unwrap(),
-                            )
-                        };
-                        true
-                    }
-                {
-                    self.translated.remove(&offset);
-                }
-            }
-            true
-        } else {
-            false
+            Some((_, incarnation)) => *incarnation += 1,
+            None => self.incarnations.push(((handle, index), 1)),
+        }
+        GENERATION.fetch_add(1, Ordering::Release);
+
+        if let Some((_, r, _)) = self.remove_translation(offset) {
+            Self::unmap_pages(r);
         }
+        Ok(())
     }
 
-    pub fn resize_pool(&mut self, name: &str, new_size: u64) -> Option<(u64, u64, u64)> {
-        let (handle, index) = self.ensure_pool_is_mapped_if_existent(name)?;
-        let pmem = self.pmems.iter_mut().find(|p| p.info.handle == handle)?;
-        let entry = pmem.pools.get(index)?;
+    pub fn resize_pool(&mut self, name: &str, new_size: u64) -> Result<(u64, u64, u64), PmemError> {
+        let id = self.find_pool(name)?;
+        self.resize_pool_by_id(id, new_size)
+    }
+
+    pub fn resize_pool_by_id(
+        &mut self,
+        id: PoolId,
+        new_size: u64,
+    ) -> Result<(u64, u64, u64), PmemError> {
+        self.ensure_pool_is_mapped(id, true)?;
+        let PoolId { handle, index, .. } = id;
+        let pools = &mut self.pmem_mut(handle)?.pools;
+        let entry = pools.get(index).ok_or(PmemError::NotFound)?;
 
         let old_offset = entry.offset();
         let old_len = entry.len();
@@ -157,17 +283,22 @@ impl Manager {
         let mut new_offset = None;
 
         if old_real_len < new_size {
-            if !pmem.pools.reallocate(index, new_size) {
-                return None;
-            }
+            pools.reallocate(index, new_size)?;
 
-            let (_, old_pages) = self.translated.remove(&old_offset)?;
+            let (_, old_pages, _) = self
+                .remove_translation(old_offset)
+                .ok_or(PmemError::NotFound)?;
 
-            let (handle, index) = self.ensure_pool_is_mapped_if_existent(name)?;
-            let pmem = self.pmems.iter_mut().find(|p| p.info.handle == handle)?;
-            let entry = pmem.pools.get(index)?;
+            self.ensure_pool_is_mapped(id, true)?;
+            let entry = self
+                .pmem(handle)?
+                .pools
+                .get(index)
+                .ok_or(PmemError::NotFound)?;
 
-            let (_, new_pages) = self.translated.get(&entry.offset())?;
+            let (_, new_pages, _) = self
+                .translation(entry.offset())
+                .ok_or(PmemError::NotFound)?;
             new_offset = Some(entry.offset());
 
             unsafe {
@@ -191,81 +322,247 @@ impl Manager {
                 );
             }
 
-            if !USE_HEAP_INSTEAD_OF_PMEM {
-                vmem::MANAGER
-                    .lock()
-                    .get_mut()
-                    .unwrap()
-                    .deallocate::<table::PageSize>(old_pages);
-            } else {
-                unsafe {
-                    dealloc(
-                        old_pages.start.start_address().as_u64() as *mut u8,
-                        Layout::from_size_align(
-                            old_real_len as usize,
-                            table::PageSize::SIZE as usize,
-                        )
-                        .unwrap(),
-                    )
-                };
+            Self::unmap_pages(old_pages);
+        } else if old_len != new_size {
+            pools.resize(index, new_size)?;
+
+            let frames = pools.get(index).ok_or(PmemError::NotFound)?.frames();
+            if let Some((_, (_, pages, _))) = self
+                .translated
+                .iter_mut()
+                .find(|(offset, _)| *offset == old_offset)
+            {
+                let unused = pages.start + frames;
+                if !USE_HEAP_INSTEAD_OF_PMEM && unused < pages.end {
+                    Self::unmap_pages(Page::range(unused, pages.end));
+                    pages.end = unused;
+                }
+            }
+        }
+
+        GENERATION.fetch_add(1, Ordering::Release);
+
+        self.translation(new_offset.unwrap_or(old_offset))
+            .map(|(_, r, _)| r.start.start_address().as_u64())
+            .map(|addr| (addr, new_size, old_len))
+            .ok_or(PmemError::NotFound)
+    }
+
+    pub fn rename_pool(&mut self, old_name: &str, new_name: &str) -> Result<(), PmemError> {
+        table::check_name(new_name)?;
+        let PoolId { handle, index, .. } = self.find_pool(old_name)?;
+        if old_name == new_name {
+            return Ok(());
+        }
+        let owner = Owner::Pool(to_owned(new_name)?);
+
+        // an existing pool with the new name gets replaced, like rename(2) does
+        if self.find_pool(new_name).is_ok() {
+            self.destroy_pool(new_name)?;
+        }
+
+        let pools = &mut self.pmem_mut(handle)?.pools;
+        pools.rename(index, new_name)?;
+        let offset = pools.get(index).ok_or(PmemError::NotFound)?.offset();
+
+        if let Some((_, pages, _)) = self.translation(offset) {
+            if let Some(manager) = vmem::MANAGER.lock().get_mut() {
+                manager.set_owner(pages.start.start_address(), owner);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drains the write queues of the NVDIMMs' memory controllers, so that
+    /// flushed cache lines have reached persistent memory. Devices without
+    /// known persistence get all caches written back first.
+    pub fn flush_write_queues(&self) {
+        if self
+            .pmems
+            .iter()
+            .any(|pmem| pmem.info.persistence == Persistence::Unknown)
+        {
+            unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+        }
+        unsafe { _mm_sfence() };
+        for addr in self.pmems.iter().filter_map(|pmem| pmem.flush_hint) {
+            unsafe { ptr::write_volatile(addr.as_mut_ptr::<u64>(), 0) };
+        }
+        unsafe { _mm_sfence() };
+    }
+
+    /// Returns the NVDIMMs with the tables of their pools.
+    pub fn devices(&self) -> impl Iterator<Item = (&NfitDevice, &Table)> {
+        self.pmems.iter().map(|pmem| (&pmem.info, &pmem.pools))
+    }
+
+    fn pmem(&self, handle: u32) -> Result<&ManagedPmem, PmemError> {
+        self.pmems
+            .iter()
+            .find(|pmem| pmem.info.handle == handle)
+            .ok_or(PmemError::NotFound)
+    }
+
+    fn pmem_mut(&mut self, handle: u32) -> Result<&mut ManagedPmem, PmemError> {
+        self.pmems
+            .iter_mut()
+            .find(|pmem| pmem.info.handle == handle)
+            .ok_or(PmemError::NotFound)
+    }
+
+    pub fn find_pool(&self, name: &str) -> Result<PoolId, PmemError> {
+        self.pmems
+            .iter()
+            .find_map(|pmem| {
+                pmem.pools
+                    .entries()
+                    .into_iter()
+                    .find(|entry| entry.name() == name)
+                    .map(|entry| (pmem.info.handle, entry.index()))
+            })
+            .map(|(handle, index)| PoolId {
+                handle,
+                index,
+                incarnation: self.incarnation(handle, index),
+            })
+            .ok_or(PmemError::NotFound)
+    }
+
+    fn incarnation(&self, handle: u32, index: usize) -> u64 {
+        self.incarnations
+            .iter()
+            .find(|(key, _)| *key == (handle, index))
+            .map_or(0, |&(_, incarnation)| incarnation)
+    }
+
+    fn ensure_pool_is_mapped(&mut self, id: PoolId, writable: bool) -> Result<(), PmemError> {
+        let PoolId { handle, index, .. } = id;
+        if id.incarnation != self.incarnation(handle, index) {
+            return Err(PmemError::NotFound);
+        }
+        let pmem = self
+            .pmems
+            .iter()
+            .find(|pmem| pmem.info.handle == handle)
+            .ok_or(PmemError::NotFound)?;
+        let entry = pmem.pools.get(index).ok_or(PmemError::NotFound)?;
+
+        let _site = tracking::site("pmem");
+        if let Some((_, (_, pages, mapped_writable))) = self
+            .translated
+            .iter_mut()
+            .find(|(offset, _)| *offset == entry.offset())
+        {
+            if writable && !*mapped_writable {
+                Self::make_writable(*pages)?;
+                *mapped_writable = true;
             }
+        } else {
+            self.translated.try_reserve(1)?;
+            let r = Self::map_pages(
+                pmem.info.phys_addr + entry.offset(),
+                entry.frames(),
+                entry.name(),
+                writable,
+            )?;
+            self.translated
+                .push((entry.offset(), (handle, r, writable)));
+
+            trace!(
+                "Mapped pool '{}' to 0x{:012x}-0x{:012x}",
+                entry.name(),
+                r.start.start_address().as_u64(),
+                r.start.start_address().as_u64() + (r.end - r.start) * table::PageSize::SIZE,
+            );
         }
 
+        Ok(())
+    }
+
+    fn translation(&self, offset: u64) -> Option<&Translation> {
         self.translated
-            .get(&new_offset.unwrap_or(old_offset))
-            .map(|(_, r)| r.start.start_address().as_u64())
-            .map(|addr| (addr, old_len.max(new_size), old_len))
+            .iter()
+            .find(|(o, _)| *o == offset)
+            .map(|(_, translation)| translation)
     }
 
-    fn ensure_pool_is_mapped_if_existent(&mut self, name: &str) -> Option<(u32, usize)> {
-        self.pmems.iter_mut().find_map(|pmem| {
-            pmem.pools
-                .entries()
-                .into_iter()
-                .find(|entry| entry.name() == name)
-                .and_then(|entry| {
-                    self.translated
-                        .contains_key(&entry.offset())
-                        .then_some(())
-                        .or_else(|| {
-                            if !USE_HEAP_INSTEAD_OF_PMEM {
-                                vmem::MANAGER
-                                    .lock()
-                                    .get_mut()
-                                    .unwrap()
-                                    .allocate::<table::PageSize>(
-                                        pmem.info.phys_addr + entry.offset(),
-                                        entry.frames(),
-                                    )
-                            } else {
-                                let ptr = unsafe {
-                                    alloc(
-                                        Layout::from_size_align(
-                                            entry.real_len() as usize,
-                                            table::PageSize::SIZE as usize,
-                                        )
-                                        .unwrap(),
-                                    )
-                                };
-                                let first =
-                                    Page::from_start_address(VirtAddr::new(ptr as u64)).unwrap();
-                                Some(Page::range(first, first + entry.frames()))
-                            }
-                            .map(|r| {
-                                self.translated
-                                    .entry(entry.offset())
-                                    .or_insert((pmem.info.handle, r));
-                                trace!(
-                                    "Mapped pool '{}' to 0x{:012x}-0x{:012x}",
-                                    entry.name(),
-                                    r.start.start_address().as_u64(),
-                                    r.start.start_address().as_u64()
-                                        + (r.end - r.start) * table::PageSize::SIZE,
-                                );
-                            })
-                        })
-                        .map(|_| (pmem.info.handle, entry.index()))
-                })
-        })
+    fn remove_translation(&mut self, offset: u64) -> Option<Translation> {
+        let i = self.translated.iter().position(|(o, _)| *o == offset)?;
+        Some(self.translated.swap_remove(i).1)
     }
+
+    fn map_pages(
+        phys_addr: PhysAddr,
+        frames: u64,
+        name: &str,
+        writable: bool,
+    ) -> Result<PageRange<table::PageSize>, PmemError> {
+        if !USE_HEAP_INSTEAD_OF_PMEM {
+            let owner = Owner::Pool(to_owned(name)?);
+            let attributes = Attributes::new().writable(writable);
+            vmem::MANAGER
+                .lock()
+                .get_mut()
+                .unwrap()
+                .allocate_guarded::<table::PageSize>(phys_addr, frames, attributes, owner)
+                .ok_or(PmemError::OutOfVirtualMemory)
+        } else {
+            let ptr = unsafe { alloc(Self::heap_layout(frames)) };
+            if ptr.is_null() {
+                return Err(PmemError::OutOfVirtualMemory);
+            }
+
+            let first = Page::from_start_address(VirtAddr::new(ptr as u64)).unwrap();
+            Ok(Page::range(first, first + frames))
+        }
+    }
+
+    /// Lets a pool that was mapped read-only be written.
+    fn make_writable(pages: PageRange<table::PageSize>) -> Result<(), PmemError> {
+        if !USE_HEAP_INSTEAD_OF_PMEM {
+            vmem::MANAGER
+                .lock()
+                .get_mut()
+                .unwrap()
+                .set_attributes(
+                    pages.start.start_address()..pages.end.start_address(),
+                    Attributes::new(),
+                )
+                .map_err(|_| PmemError::NotFound)?;
+        }
+        Ok(())
+    }
+
+    fn unmap_pages(pages: PageRange<table::PageSize>) {
+        if !USE_HEAP_INSTEAD_OF_PMEM {
+            vmem::MANAGER
+                .lock()
+                .get_mut()
+                .unwrap()
+                .deallocate::<table::PageSize>(pages);
+        } else {
+            unsafe {
+                dealloc(
+                    pages.start.start_address().as_mut_ptr(),
+                    Self::heap_layout(pages.end - pages.start),
+                )
+            };
+        }
+    }
+
+    fn heap_layout(frames: u64) -> Layout {
+        Layout::from_size_align(
+            (frames * table::PageSize::SIZE) as usize,
+            table::PageSize::SIZE as usize,
+        )
+        .unwrap()
+    }
+}
+
+/// Copies a pool name, failing instead of panicking if the heap is exhausted.
+fn to_owned(name: &str) -> Result<String, PmemError> {
+    let mut owned = String::new();
+    owned.try_reserve_exact(name.len())?;
+    owned.push_str(name);
+    Ok(owned)
 }
@@ -3,9 +3,34 @@ use crate::nfit::NfitEntry;
 use crate::nfit::SpaRangeEntry;
 use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
 use core::fmt;
+use x86_64::structures::paging::{PageSize, Size4KiB};
 use x86_64::PhysAddr;
 
+/// E820 type of persistent memory since ACPI 6.
+const E820_PMEM: u32 = 7;
+/// E820 type of persistent memory before ACPI 6, still used by hypervisors
+/// and Linux' `memmap=nn!ss`.
+const E820_PRAM: u32 = 12;
+const EFI_PERSISTENT_MEMORY: u32 = 14;
+
+/// Handles of devices found in the memory map start here, away from the
+/// handles the NFIT assigns.
+const LEGACY_HANDLE_BASE: u32 = 0xffff_0000;
+
+/// What it takes for stores to become persistent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persistence {
+    /// The platform flushes the memory controller's write queues on power
+    /// loss, as described by the NFIT, so fenced stores that left the CPU
+    /// caches are persistent.
+    Adr,
+    /// Nothing is known about the device, so all caches are written back to
+    /// be safe.
+    Unknown,
+}
+
 #[derive(Clone)]
 pub struct NfitDevice {
     pub handle: u32,
@@ -13,6 +38,7 @@ pub struct NfitDevice {
     pub phys_addr: PhysAddr,
     pub size: u64,
     pub flush_addresses: Option<Vec<PhysAddr>>,
+    pub persistence: Persistence,
 }
 
 impl Default for NfitDevice {
@@ -23,6 +49,7 @@ impl Default for NfitDevice {
             phys_addr: PhysAddr::new(0),
             size: Default::default(),
             flush_addresses: Default::default(),
+            persistence: Persistence::Adr,
         }
     }
 }
@@ -58,7 +85,9 @@ impl fmt::Debug for NfitDevice {
             for addr in addrs.iter() {
                 write!(f, "0x{:012x},", addr.as_u64())?;
             }
+            write!(f, "{}", nl)?;
         }
+        write!(f, "{}persistence: {:?},{}", tb, self.persistence, nl)?;
 
         write!(f, "}}")
     }
@@ -110,3 +139,40 @@ pub fn get_devices(nfit: &nfit::Nfit) -> Vec<NfitDevice> {
     res.sort_unstable_by_key(|d| d.phys_addr);
     res
 }
+
+/// Returns the persistent memory regions of the bootloader's memory map that
+/// aren't described by any of the NFIT devices.
+pub fn get_legacy_devices(
+    regions: &[MemoryRegion],
+    nfit_devices: &[NfitDevice],
+) -> Vec<NfitDevice> {
+    regions
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.kind,
+                MemoryRegionKind::UnknownBios(E820_PMEM | E820_PRAM)
+                    | MemoryRegionKind::UnknownUefi(EFI_PERSISTENT_MEMORY)
+            )
+        })
+        .map(|r| {
+            let start = x86_64::align_up(r.start, Size4KiB::SIZE);
+            start..x86_64::align_down(r.end, Size4KiB::SIZE).max(start)
+        })
+        .filter(|r| r.end - r.start > Size4KiB::SIZE)
+        .filter(|r| {
+            nfit_devices.iter().all(|d| {
+                let start = d.phys_addr.as_u64();
+                r.end <= start || start + d.size <= r.start
+            })
+        })
+        .enumerate()
+        .map(|(i, r)| NfitDevice {
+            handle: LEGACY_HANDLE_BASE + i as u32,
+            phys_addr: PhysAddr::new(r.start),
+            size: r.end - r.start,
+            persistence: Persistence::Unknown,
+            ..NfitDevice::default()
+        })
+        .collect()
+}
@@ -0,0 +1,142 @@
+use crate::errno;
+use alloc::collections::TryReserveError;
+use core::alloc::AllocError;
+use core::ffi::c_int;
+use core::fmt;
+use embedded_io::ErrorKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmemError {
+    /// There is no pool with the given name.
+    NotFound,
+    /// A pool with the given name exists already.
+    AlreadyExists,
+    /// The pool name is empty.
+    InvalidName,
+    /// The pool name doesn't fit into a table entry.
+    NameTooLong,
+    /// All entries of the pool tables are taken.
+    TableFull,
+    /// None of the NVDIMMs has a free region large enough.
+    NoSpace,
+    /// There is no virtual address range left to map the pool into.
+    OutOfVirtualMemory,
+    /// The pool wasn't opened for reading.
+    NotReadable,
+    /// The pool wasn't opened for writing.
+    NotWritable,
+    /// A seek to a negative or overflowing position.
+    InvalidSeek,
+    /// A pool can't be larger than the address space.
+    TooLarge,
+    /// The file descriptor isn't open.
+    BadDescriptor,
+    /// All slots of the file descriptor table are taken.
+    TooManyOpenFiles,
+    /// The pool is too small for a heap, or its heap is damaged.
+    InvalidHeap,
+    /// The kernel heap is exhausted.
+    OutOfMemory,
+}
+
+impl PmemError {
+    pub fn errno(self) -> c_int {
+        match self {
+            Self::NotFound => errno::ENOENT,
+            Self::AlreadyExists => errno::EEXIST,
+            Self::InvalidName => errno::EINVAL,
+            Self::NameTooLong => errno::ENAMETOOLONG,
+            Self::TableFull => errno::ENFILE,
+            Self::NoSpace => errno::ENOSPC,
+            Self::OutOfVirtualMemory => errno::ENOMEM,
+            Self::NotReadable | Self::NotWritable => errno::EBADF,
+            Self::InvalidSeek => errno::EINVAL,
+            Self::TooLarge => errno::EFBIG,
+            Self::BadDescriptor => errno::EBADF,
+            Self::TooManyOpenFiles => errno::EMFILE,
+            Self::InvalidHeap => errno::EINVAL,
+            Self::OutOfMemory => errno::ENOMEM,
+        }
+    }
+}
+
+impl fmt::Display for PmemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotFound => "pool not found",
+            Self::AlreadyExists => "pool already exists",
+            Self::InvalidName => "invalid pool name",
+            Self::NameTooLong => "pool name too long",
+            Self::TableFull => "pool table full",
+            Self::NoSpace => "no space left on persistent memory",
+            Self::OutOfVirtualMemory => "no virtual memory left to map pool",
+            Self::NotReadable => "pool not opened for reading",
+            Self::NotWritable => "pool not opened for writing",
+            Self::InvalidSeek => "invalid seek to a negative or overflowing position",
+            Self::TooLarge => "pool too large",
+            Self::BadDescriptor => "bad file descriptor",
+            Self::TooManyOpenFiles => "too many open files",
+            Self::InvalidHeap => "pool holds no valid heap",
+            Self::OutOfMemory => "out of kernel memory",
+        })
+    }
+}
+
+impl From<AllocError> for PmemError {
+    fn from(_: AllocError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+impl From<TryReserveError> for PmemError {
+    fn from(_: TryReserveError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+impl embedded_io::Error for PmemError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::NotFound => ErrorKind::NotFound,
+            Self::AlreadyExists => ErrorKind::AlreadyExists,
+            Self::InvalidName | Self::NameTooLong | Self::InvalidSeek | Self::BadDescriptor => {
+                ErrorKind::InvalidInput
+            }
+            Self::TableFull
+            | Self::NoSpace
+            | Self::OutOfVirtualMemory
+            | Self::TooLarge
+            | Self::TooManyOpenFiles
+            | Self::OutOfMemory => ErrorKind::OutOfMemory,
+            Self::NotReadable | Self::NotWritable => ErrorKind::PermissionDenied,
+            Self::InvalidHeap => ErrorKind::InvalidData,
+        }
+    }
+}
+
+#[test_case]
+fn errors_map_to_known_errno_values() {
+    use PmemError::*;
+
+    let expected = [
+        (NotFound, errno::ENOENT),
+        (AlreadyExists, errno::EEXIST),
+        (InvalidName, errno::EINVAL),
+        (NameTooLong, errno::ENAMETOOLONG),
+        (TableFull, errno::ENFILE),
+        (NoSpace, errno::ENOSPC),
+        (OutOfVirtualMemory, errno::ENOMEM),
+        (NotReadable, errno::EBADF),
+        (NotWritable, errno::EBADF),
+        (InvalidSeek, errno::EINVAL),
+        (TooLarge, errno::EFBIG),
+        (BadDescriptor, errno::EBADF),
+        (TooManyOpenFiles, errno::EMFILE),
+        (InvalidHeap, errno::EINVAL),
+        (OutOfMemory, errno::ENOMEM),
+    ];
+    for (err, value) in expected {
+        assert_eq!(err.errno(), value, "{:?}", err);
+        assert_ne!(errno::message(value), errno::message(-1), "{:?}", err);
+    }
+}
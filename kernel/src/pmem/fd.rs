@@ -0,0 +1,110 @@
+//! Integer file descriptors for open pools.
+//!
+//! Descriptors index into a kernel-wide table, so a stale or forged
+//! descriptor is reported as [`PmemError::BadDescriptor`] instead of
+//! touching freed memory. Duplicated descriptors share one [`OpenFile`],
+//! including its position, like they do on POSIX systems.
+
+use super::file::PoolFile;
+use super::PmemError;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub static FILES: Mutex<FileTable> = Mutex::new(FileTable::new());
+
+/// Highest number of descriptors that can be open at once.
+pub const MAX_OPEN: usize = 256;
+
+pub type Fd = usize;
+
+/// State shared by all descriptors referring to the same open pool.
+#[derive(Debug)]
+pub struct OpenFile {
+    pub file: PoolFile,
+    /// Set by a read that hit the end of the pool.
+    pub eof: bool,
+    /// Set by a read or write that failed.
+    pub error: bool,
+}
+
+impl OpenFile {
+    pub fn new(file: PoolFile) -> Self {
+        OpenFile {
+            file,
+            eof: false,
+            error: false,
+        }
+    }
+}
+
+pub struct FileTable {
+    slots: Vec<Option<Arc<Mutex<OpenFile>>>>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        FileTable { slots: Vec::new() }
+    }
+
+    /// Stores an open pool under the lowest free descriptor.
+    pub fn insert(&mut self, file: PoolFile) -> Result<Fd, PmemError> {
+        self.insert_shared(Arc::try_new(Mutex::new(OpenFile::new(file)))?)
+    }
+
+    /// Returns the open pool behind a descriptor.
+    pub fn get(&self, fd: Fd) -> Result<Arc<Mutex<OpenFile>>, PmemError> {
+        self.slots
+            .get(fd)
+            .and_then(Option::as_ref)
+            .cloned()
+            .ok_or(PmemError::BadDescriptor)
+    }
+
+    /// Creates a second descriptor for the same open pool.
+    pub fn dup(&mut self, fd: Fd) -> Result<Fd, PmemError> {
+        let file = self.get(fd)?;
+        self.insert_shared(file)
+    }
+
+    /// Makes `new_fd` refer to the same open pool as `fd`, closing whatever
+    /// `new_fd` referred to before.
+    pub fn dup2(&mut self, fd: Fd, new_fd: Fd) -> Result<Fd, PmemError> {
+        let file = self.get(fd)?;
+        if new_fd >= MAX_OPEN {
+            return Err(PmemError::BadDescriptor);
+        }
+        if fd != new_fd {
+            if self.slots.len() <= new_fd {
+                self.slots.try_reserve(new_fd + 1 - self.slots.len())?;
+                self.slots.resize(new_fd + 1, None);
+            }
+            self.slots[new_fd] = Some(file);
+        }
+        Ok(new_fd)
+    }
+
+    /// Releases a descriptor. The pool is closed once its last descriptor is.
+    pub fn close(&mut self, fd: Fd) -> Result<(), PmemError> {
+        self.slots
+            .get_mut(fd)
+            .and_then(Option::take)
+            .map(|_| ())
+            .ok_or(PmemError::BadDescriptor)
+    }
+
+    fn insert_shared(&mut self, file: Arc<Mutex<OpenFile>>) -> Result<Fd, PmemError> {
+        match self.slots.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.slots[fd] = Some(file);
+                Ok(fd)
+            }
+            None if self.slots.len() < MAX_OPEN => {
+                self.slots.try_reserve(1)?;
+                self.slots.push(Some(file));
+                Ok(self.slots.len() - 1)
+            }
+            None => Err(PmemError::TooManyOpenFiles),
+        }
+    }
+}
@@ -1,149 +1,576 @@
-use crate::pmem;
-use alloc::borrow::ToOwned;
-use alloc::boxed::Box;
+use crate::errno;
+use crate::pmem::fd::{OpenFile, FILES};
+use crate::pmem::{self, file::OpenOptions, file::PoolFile, table, PmemError};
 use alloc::slice;
-use alloc::string::String;
-use core::ffi::{c_char, c_int, c_ulonglong, c_void, CStr};
-use core::mem::MaybeUninit;
+use alloc::sync::Arc;
+use core::ffi::{c_char, c_int, c_long, c_uint, c_ulonglong, c_void, CStr};
 use core::ptr;
-use corundum::ll;
+use embedded_io::SeekFrom;
+use spin::Mutex;
+use x86_64::structures::paging::PageSize;
 
-struct File {
-    filename: String,
-    mode: String,
-    pos: u64,
+const EOF: c_int = -1;
+const SEEK_SET: c_int = 0;
+const SEEK_CUR: c_int = 1;
+const SEEK_END: c_int = 2;
+
+const O_ACCMODE: c_int = 0o3;
+const O_RDONLY: c_int = 0o0;
+const O_WRONLY: c_int = 0o1;
+const O_RDWR: c_int = 0o2;
+const O_CREAT: c_int = 0o100;
+const O_EXCL: c_int = 0o200;
+const O_TRUNC: c_int = 0o1000;
+const O_APPEND: c_int = 0o2000;
+
+const S_IFREG: c_uint = 0o100000;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+/// `struct stat` as laid out on x86_64 Linux.
+#[repr(C)]
+struct Stat {
+    st_dev: u64,
+    st_ino: u64,
+    st_nlink: u64,
+    st_mode: c_uint,
+    st_uid: c_uint,
+    st_gid: c_uint,
+    __pad0: c_int,
+    st_rdev: u64,
+    st_size: c_long,
+    st_blksize: c_long,
+    st_blocks: c_long,
+    st_atime: c_long,
+    st_atime_nsec: c_long,
+    st_mtime: c_long,
+    st_mtime_nsec: c_long,
+    st_ctime: c_long,
+    st_ctime_nsec: c_long,
+    __unused: [c_long; 3],
+}
+
+/// Translates an fopen mode string like `r`, `w+` or `ab` into open options.
+fn parse_mode(mode: &str) -> Option<OpenOptions> {
+    let update = mode.contains('+');
+    let mut options = OpenOptions::new();
+    match mode.chars().next()? {
+        'r' => options.read(true).write(update),
+        'w' => options.read(update).write(true).create(true).truncate(true),
+        'a' => options.read(update).append(true).create(true),
+        _ => return None,
+    };
+    Some(options)
+}
+
+/// Looks up an open descriptor and sets `errno` if there is none.
+fn file_from_fd(fd: c_int) -> Option<Arc<Mutex<OpenFile>>> {
+    let file = usize::try_from(fd)
+        .map_err(|_| PmemError::BadDescriptor)
+        .and_then(|fd| FILES.lock().get(fd));
+    match file {
+        Ok(file) => Some(file),
+        Err(err) => fail(err, None),
+    }
+}
+
+/// A `FILE*` is the descriptor plus one, so that descriptor 0 isn't null.
+fn fd_from_ptr(file: *mut c_void) -> c_int {
+    (file as usize)
+        .checked_sub(1)
+        .and_then(|fd| c_int::try_from(fd).ok())
+        .unwrap_or(-1)
+}
+
+fn file_from_ptr(file: *mut c_void) -> Option<Arc<Mutex<OpenFile>>> {
+    file_from_fd(fd_from_ptr(file))
+}
+
+/// Stores an open pool in the descriptor table.
+fn insert(file: Result<PoolFile, PmemError>) -> Result<usize, PmemError> {
+    file.and_then(|file| FILES.lock().insert(file))
+}
+
+/// # Safety
+///
+/// `s` must be null or point to a NUL-terminated string. Sets `errno` if
+/// the string is null or not valid UTF-8.
+unsafe fn str_from_ptr<'a>(s: *const c_char) -> Option<&'a str> {
+    let s = (!s.is_null())
+        .then(|| CStr::from_ptr(s).to_str().ok())
+        .flatten();
+    if s.is_none() {
+        errno::set(errno::EINVAL);
+    }
+    s
+}
+
+/// Sets `errno` according to `err` and returns `ret`.
+fn fail<T>(err: PmemError, ret: T) -> T {
+    errno::set(err.errno());
+    ret
+}
+
+fn seek_from(offset: c_long, whence: c_int) -> Option<SeekFrom> {
+    match whence {
+        SEEK_SET if offset >= 0 => Some(SeekFrom::Start(offset as u64)),
+        SEEK_CUR => Some(SeekFrom::Current(offset)),
+        SEEK_END => Some(SeekFrom::End(offset)),
+        _ => None,
+    }
 }
 
 #[no_mangle]
 extern "C" fn fopen(filename: *const c_char, mode: *const c_char) -> *mut c_void {
-    let Ok(filename) = unsafe { CStr::from_ptr(filename) }.to_str() else {
+    let Some(filename) = (unsafe { str_from_ptr(filename) }) else {
         return ptr::null_mut();
     };
-    let Ok(mode) = unsafe { CStr::from_ptr(mode) }.to_str() else {
+    let Some(options) = (unsafe { str_from_ptr(mode) }).and_then(parse_mode) else {
+        errno::set(errno::EINVAL);
         return ptr::null_mut();
     };
-    let mut mgr = pmem::MANAGER.lock();
-
-    if mgr
-        .get_pool(filename)
-        .or_else(|| {
-            if mode.contains(['w', 'a']) {
-                mgr.create_pool(filename, 0)
-            } else {
-                None
-            }
-        })
-        .is_some()
-    {
-        Box::into_raw(Box::new(File {
-            filename: filename.to_owned(),
-            mode: mode.to_owned(),
-            pos: 0,
-        })) as *mut c_void
-    } else {
-        ptr::null_mut()
+
+    match insert(options.open(filename)) {
+        Ok(fd) => (fd + 1) as *mut c_void,
+        Err(err) => fail(err, ptr::null_mut()),
+    }
+}
+
+#[no_mangle]
+extern "C" fn fread(buf: *mut c_void, size: usize, count: usize, file: *mut c_void) -> usize {
+    let Some(file) = file_from_ptr(file) else {
+        return 0;
+    };
+    let Some(buf_size) = size.checked_mul(count) else {
+        return 0;
+    };
+    if buf_size == 0 {
+        return 0;
+    }
+
+    let mut file = file.lock();
+    let buf = unsafe { slice::from_raw_parts_mut(buf as *mut u8, buf_size) };
+    let amt = match file.file.read(buf) {
+        Ok(amt) => amt,
+        Err(err) => {
+            file.error = true;
+            return fail(err, 0);
+        }
+    };
+    if amt < buf_size {
+        file.eof = true;
     }
+
+    amt / size
 }
 
 #[no_mangle]
 extern "C" fn fwrite(buf: *const c_void, size: usize, count: usize, file: *mut c_void) -> usize {
-    let file = unsafe { Box::<File>::from_raw(file as *mut File) };
-    let buf_size = size * count;
-    let written = if file.mode.contains(['w', 'a', '+']) {
-        pmem::MANAGER
-            .lock()
-            .get_pool(&file.filename)
-            .and_then(|(addr, size)| {
-                unsafe { slice::from_raw_parts_mut(addr as *mut MaybeUninit<u8>, size as usize) }
-                    .get_mut(file.pos as usize..)
-                    .map(|s| {
-                        let amt = buf_size.min(s.len());
-                        let buf = unsafe { slice::from_raw_parts_mut(buf as *mut _, buf_size) };
-                        s[..amt].copy_from_slice(&buf[..amt]);
-                        amt
-                    })
-            })
-            .unwrap_or(0)
-    } else {
-        0
-    };
-
-    Box::leak(file);
-    written
+    let Some(file) = file_from_ptr(file) else {
+        return 0;
+    };
+    let Some(buf_size) = size.checked_mul(count) else {
+        return 0;
+    };
+    if buf_size == 0 {
+        return 0;
+    }
+
+    let mut file = file.lock();
+    let buf = unsafe { slice::from_raw_parts(buf as *const u8, buf_size) };
+    match file.file.write(buf) {
+        Ok(n) => n / size,
+        Err(err) => {
+            file.error = true;
+            fail(err, 0)
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn fseek(file: *mut c_void, offset: c_long, whence: c_int) -> c_int {
+    let Some(file) = file_from_ptr(file) else {
+        return -1;
+    };
+
+    let Some(pos) = seek_from(offset, whence) else {
+        return fail(PmemError::InvalidSeek, -1);
+    };
+
+    let mut file = file.lock();
+    match file.file.seek(pos) {
+        Ok(_) => {
+            file.eof = false;
+            0
+        }
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn ftell(file: *mut c_void) -> c_long {
+    match file_from_ptr(file) {
+        Some(file) => file.lock().file.position() as c_long,
+        None => -1,
+    }
+}
+
+#[no_mangle]
+extern "C" fn rewind(file: *mut c_void) {
+    if let Some(file) = file_from_ptr(file) {
+        let mut file = file.lock();
+        let _ = file.file.seek(SeekFrom::Start(0));
+        file.eof = false;
+    }
+}
+
+#[no_mangle]
+extern "C" fn feof(file: *mut c_void) -> c_int {
+    match file_from_ptr(file) {
+        Some(file) => file.lock().eof as c_int,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+extern "C" fn ferror(file: *mut c_void) -> c_int {
+    match file_from_ptr(file) {
+        Some(file) => file.lock().error as c_int,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+extern "C" fn clearerr(file: *mut c_void) {
+    if let Some(file) = file_from_ptr(file) {
+        let mut file = file.lock();
+        file.eof = false;
+        file.error = false;
+    }
+}
+
+#[no_mangle]
+extern "C" fn fflush(file: *mut c_void) -> c_int {
+    if file.is_null() {
+        return 0;
+    }
+    let Some(file) = file_from_ptr(file) else {
+        return EOF;
+    };
+
+    let mut file = file.lock();
+    match file.file.sync() {
+        Ok(()) => 0,
+        Err(err) => {
+            file.error = true;
+            fail(err, EOF)
+        }
+    }
 }
 
 #[no_mangle]
 extern "C" fn fclose(file: *mut c_void) -> c_int {
-    let _ = unsafe { Box::<File>::from_raw(file as *mut File) };
-    0
+    match close(fd_from_ptr(file)) {
+        0 => 0,
+        _ => EOF,
+    }
+}
+
+#[no_mangle]
+extern "C" fn fileno(file: *mut c_void) -> c_int {
+    let fd = fd_from_ptr(file);
+    match file_from_fd(fd) {
+        Some(_) => fd,
+        None => -1,
+    }
 }
 
 #[no_mangle]
 extern "C" fn remove(filename: *const c_char) -> c_int {
-    let Ok(filename) = unsafe { CStr::from_ptr(filename) }.to_str() else {
+    let Some(filename) = (unsafe { str_from_ptr(filename) }) else {
         return -1;
     };
 
-    if pmem::MANAGER.lock().destroy_pool(filename) {
-        0
-    } else {
-        -1
+    match pmem::MANAGER.lock().destroy_pool(filename) {
+        Ok(()) => 0,
+        Err(err) => fail(err, -1),
     }
 }
 
 #[no_mangle]
-extern "C" fn truncate(filename: *const c_char, length: c_ulonglong) -> c_ulonglong {
-    let Ok(filename) = unsafe { CStr::from_ptr(filename) }.to_str() else {
-        return 0;
+extern "C" fn rename(old_filename: *const c_char, new_filename: *const c_char) -> c_int {
+    let Some(old_filename) = (unsafe { str_from_ptr(old_filename) }) else {
+        return -1;
+    };
+    let Some(new_filename) = (unsafe { str_from_ptr(new_filename) }) else {
+        return -1;
     };
 
-    if let Some((addr, new_length, old_length)) = pmem::MANAGER.lock().resize_pool(filename, length)
-    {
-        let buf: &mut [MaybeUninit<u8>] =
-            unsafe { slice::from_raw_parts_mut(addr as *mut _, new_length as usize) };
-        let extended = &mut buf[old_length as usize..];
+    match pmem::MANAGER.lock().rename_pool(old_filename, new_filename) {
+        Ok(()) => 0,
+        Err(err) => fail(err, -1),
+    }
+}
 
-        if !extended.is_empty() {
-            extended.fill(MaybeUninit::zeroed());
-            ll::persist_obj(extended, true);
-        }
+/// Resizes a pool and zeroes any extended space.
+#[no_mangle]
+extern "C" fn truncate(filename: *const c_char, length: c_long) -> c_int {
+    let Some(filename) = (unsafe { str_from_ptr(filename) }) else {
+        return -1;
+    };
+    let Ok(length) = u64::try_from(length) else {
+        return fail(PmemError::InvalidSeek, -1);
+    };
 
-        new_length
-    } else {
-        length
+    match PoolFile::options()
+        .write(true)
+        .open(filename)
+        .and_then(|mut file| file.set_len(length))
+    {
+        Ok(()) => 0,
+        Err(err) => fail(err, -1),
     }
 }
 
+/// Returns the length of a pool, or 0 with `errno` set on failure.
 #[no_mangle]
 extern "C" fn size(filename: *const c_char) -> c_ulonglong {
-    let Ok(filename) = unsafe { CStr::from_ptr(filename) }.to_str() else {
+    let Some(filename) = (unsafe { str_from_ptr(filename) }) else {
         return 0;
     };
 
-    let size = pmem::MANAGER
-        .lock()
-        .get_pool(filename)
-        .map(|(_, size)| size as c_ulonglong)
-        .unwrap_or(0);
-    size
+    match pmem::MANAGER.lock().get_pool(filename, false) {
+        Ok((_, size)) => size as c_ulonglong,
+        Err(err) => fail(err, 0),
+    }
 }
 
 #[no_mangle]
 extern "C" fn map(filename: *const c_char) -> *mut c_void {
-    let Ok(filename) = unsafe { CStr::from_ptr(filename) }.to_str() else {
+    let Some(filename) = (unsafe { str_from_ptr(filename) }) else {
         return ptr::null_mut();
     };
 
-    let r = pmem::MANAGER
-        .lock()
-        .get_pool(filename)
-        .map(|(addr, _)| addr as *mut c_void)
-        .unwrap_or(ptr::null_mut());
-
-    r
+    match pmem::MANAGER.lock().get_pool(filename, true) {
+        Ok((addr, _)) => addr as *mut c_void,
+        Err(err) => fail(err, ptr::null_mut()),
+    }
 }
 
 #[no_mangle]
 extern "C" fn unmap(_addr: *mut c_void) -> c_int {
     0
 }
+
+#[no_mangle]
+extern "C" fn open(path: *const c_char, flags: c_int, _mode: c_uint) -> c_int {
+    let Some(path) = (unsafe { str_from_ptr(path) }) else {
+        return -1;
+    };
+
+    let mut options = OpenOptions::new();
+    match flags & O_ACCMODE {
+        O_RDONLY => options.read(true),
+        O_WRONLY => options.write(true),
+        O_RDWR => options.read(true).write(true),
+        _ => {
+            errno::set(errno::EINVAL);
+            return -1;
+        }
+    };
+    options
+        .append(flags & O_APPEND != 0)
+        .truncate(flags & O_TRUNC != 0)
+        .create(flags & O_CREAT != 0)
+        .create_new(flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL);
+
+    match insert(options.open(path)) {
+        Ok(fd) => fd as c_int,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize {
+    let Some(file) = file_from_fd(fd) else {
+        return -1;
+    };
+    if count == 0 {
+        return 0;
+    }
+
+    let buf = unsafe { slice::from_raw_parts_mut(buf as *mut u8, count) };
+    let res = file.lock().file.read(buf);
+    match res {
+        Ok(amt) => amt as isize,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn write(fd: c_int, buf: *const c_void, count: usize) -> isize {
+    let Some(file) = file_from_fd(fd) else {
+        return -1;
+    };
+    if count == 0 {
+        return 0;
+    }
+
+    let buf = unsafe { slice::from_raw_parts(buf as *const u8, count) };
+    let res = file.lock().file.write(buf);
+    match res {
+        Ok(amt) => amt as isize,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn lseek(fd: c_int, offset: c_long, whence: c_int) -> c_long {
+    let Some(file) = file_from_fd(fd) else {
+        return -1;
+    };
+    let Some(pos) = seek_from(offset, whence) else {
+        return fail(PmemError::InvalidSeek, -1);
+    };
+
+    let res = file.lock().file.seek(pos);
+
+    match res {
+        Ok(pos) => c_long::try_from(pos).unwrap_or_else(|_| fail(PmemError::TooLarge, -1)),
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn close(fd: c_int) -> c_int {
+    let res = usize::try_from(fd)
+        .map_err(|_| PmemError::BadDescriptor)
+        .and_then(|fd| FILES.lock().close(fd));
+    match res {
+        Ok(()) => 0,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn dup(fd: c_int) -> c_int {
+    let res = usize::try_from(fd)
+        .map_err(|_| PmemError::BadDescriptor)
+        .and_then(|fd| FILES.lock().dup(fd));
+    match res {
+        Ok(fd) => fd as c_int,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn dup2(fd: c_int, new_fd: c_int) -> c_int {
+    let res = usize::try_from(fd)
+        .ok()
+        .zip(usize::try_from(new_fd).ok())
+        .ok_or(PmemError::BadDescriptor)
+        .and_then(|(fd, new_fd)| FILES.lock().dup2(fd, new_fd));
+    match res {
+        Ok(fd) => fd as c_int,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn ftruncate(fd: c_int, length: c_long) -> c_int {
+    let Some(file) = file_from_fd(fd) else {
+        return -1;
+    };
+    let Ok(length) = u64::try_from(length) else {
+        return fail(PmemError::InvalidSeek, -1);
+    };
+
+    let res = file.lock().file.set_len(length);
+
+    match res {
+        Ok(()) => 0,
+        Err(err) => fail(err, -1),
+    }
+}
+
+#[no_mangle]
+extern "C" fn fstat(fd: c_int, buf: *mut Stat) -> c_int {
+    let Some(file) = file_from_fd(fd) else {
+        return -1;
+    };
+    if buf.is_null() {
+        errno::set(errno::EINVAL);
+        return -1;
+    }
+    let len = match file.lock().file.len() {
+        Ok(len) => len as c_long,
+        Err(err) => return fail(err, -1),
+    };
+
+    let stat = Stat {
+        st_dev: 0,
+        st_ino: 0,
+        st_nlink: 1,
+        st_mode: S_IFREG | 0o644,
+        st_uid: 0,
+        st_gid: 0,
+        __pad0: 0,
+        st_rdev: 0,
+        st_size: len,
+        st_blksize: table::PageSize::SIZE as c_long,
+        st_blocks: (len + 511) / 512,
+        st_atime: 0,
+        st_atime_nsec: 0,
+        st_mtime: 0,
+        st_mtime_nsec: 0,
+        st_ctime: 0,
+        st_ctime_nsec: 0,
+        __unused: [0; 3],
+    };
+    unsafe { buf.write(stat) };
+    0
+}
+
+/// Returns the address of the pool behind `fd` plus `offset`.
+///
+/// Pools are always mapped, so `addr`, `prot` and `flags` are ignored and
+/// the mapping becomes invalid once the pool is resized. Fails with `EINVAL`
+/// if `offset` and `length` reach past the end of the pool.
+#[no_mangle]
+extern "C" fn mmap(
+    _addr: *mut c_void,
+    length: usize,
+    _prot: c_int,
+    _flags: c_int,
+    fd: c_int,
+    offset: c_long,
+) -> *mut c_void {
+    let Some(file) = file_from_fd(fd) else {
+        return MAP_FAILED;
+    };
+    let Ok(offset) = u64::try_from(offset) else {
+        return fail(PmemError::InvalidSeek, MAP_FAILED);
+    };
+    if length == 0 || offset % table::PageSize::SIZE != 0 {
+        return fail(PmemError::InvalidSeek, MAP_FAILED);
+    }
+
+    let res = unsafe {
+        file.lock()
+            .file
+            .map()
+            .map(|pool| (pool.as_mut_ptr(), pool.len()))
+    };
+    let (addr, len) = match res {
+        Ok(pool) => pool,
+        Err(err) => return fail(err, MAP_FAILED),
+    };
+    match offset.checked_add(length as u64) {
+        Some(end) if end <= len as u64 => unsafe { addr.add(offset as usize) as *mut c_void },
+        _ => fail(PmemError::InvalidSeek, MAP_FAILED),
+    }
+}
+
+#[no_mangle]
+extern "C" fn munmap(_addr: *mut c_void, _length: usize) -> c_int {
+    0
+}
@@ -0,0 +1,312 @@
+use super::{table, PmemError, PoolId, MANAGER};
+use alloc::string::String;
+use core::cell::Cell;
+use core::slice;
+use corundum::ll;
+use embedded_io::{ErrorType, SeekFrom};
+
+/// Options for opening a pool, modeled after `std::fs::OpenOptions`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+impl OpenOptions {
+    pub const fn new() -> Self {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Every write goes to the current end of the pool. Implies `write`.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Truncates an existing pool to zero bytes. Requires `write`.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates a new pool and fails if it already exists.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    pub fn open(&self, name: &str) -> Result<PoolFile, PmemError> {
+        let write = self.write || self.append;
+        if (self.truncate || self.create || self.create_new) && !write {
+            return Err(PmemError::NotWritable);
+        }
+        table::check_name(name)?;
+        let owned = super::to_owned(name)?;
+
+        let mut mgr = MANAGER.lock();
+        let id = match mgr.find_pool(name) {
+            Ok(_) if self.create_new => return Err(PmemError::AlreadyExists),
+            Ok(id) => {
+                if self.truncate && mgr.get_pool_by_id(id, true)?.1 > 0 {
+                    mgr.resize_pool_by_id(id, 0)?;
+                }
+                id
+            }
+            Err(PmemError::NotFound) if self.create || self.create_new => {
+                mgr.create_pool(name, 0)?;
+                mgr.find_pool(name)?
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(PoolFile {
+            id,
+            name: owned,
+            read: self.read,
+            write,
+            append: self.append,
+            pos: 0,
+            mapping: Cell::new(None),
+        })
+    }
+}
+
+/// An open pool that can be read and written like a file.
+///
+/// Writes are persisted before they return, so there is no buffering involved.
+/// The pool is tracked by its id, so renaming it doesn't affect open files.
+#[derive(Debug)]
+pub struct PoolFile {
+    id: PoolId,
+    name: String,
+    read: bool,
+    write: bool,
+    append: bool,
+    pos: u64,
+    /// Address and length of the pool, valid as long as the pool
+    /// generation hasn't changed.
+    mapping: Cell<Option<(u64, u64, u64)>>,
+}
+
+impl PoolFile {
+    /// Opens an existing pool for reading.
+    pub fn open(name: &str) -> Result<Self, PmemError> {
+        OpenOptions::new().read(true).open(name)
+    }
+
+    /// Opens a pool for writing, creating it if it doesn't exist and
+    /// truncating it otherwise.
+    pub fn create(name: &str) -> Result<Self, PmemError> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(name)
+    }
+
+    pub fn options() -> OpenOptions {
+        OpenOptions::new()
+    }
+
+    pub fn id(&self) -> PoolId {
+        self.id
+    }
+
+    /// Returns the name the pool was opened with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> Result<u64, PmemError> {
+        self.mapping().map(|(_, len)| len)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, PmemError> {
+        self.len().map(|len| len == 0)
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, PmemError> {
+        if !self.read {
+            return Err(PmemError::NotReadable);
+        }
+
+        let (addr, len) = self.mapping()?;
+
+        let amt = (buf.len() as u64).min(len.saturating_sub(self.pos)) as usize;
+        if amt > 0 {
+            let src = unsafe { slice::from_raw_parts((addr + self.pos) as *const u8, amt) };
+            buf[..amt].copy_from_slice(src);
+            self.pos += amt as u64;
+        }
+
+        Ok(amt)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, PmemError> {
+        if !self.write {
+            return Err(PmemError::NotWritable);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (mut addr, len) = self.mapping()?;
+
+        if self.append {
+            self.pos = len;
+        }
+
+        let end = self
+            .pos
+            .checked_add(buf.len() as u64)
+            .ok_or(PmemError::TooLarge)?;
+        if end > len {
+            (addr, _, _) = MANAGER.lock().resize_pool_by_id(self.id, end)?;
+            self.remember(addr, end);
+
+            // a write behind the end of the pool leaves a hole that reads as zeros
+            if self.pos > len {
+                unsafe { zero(addr + len, self.pos - len) };
+            }
+        }
+
+        let dst = unsafe { slice::from_raw_parts_mut((addr + self.pos) as *mut u8, buf.len()) };
+        dst.copy_from_slice(buf);
+        ll::persist_obj(dst, true);
+
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64, PmemError> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len()?.checked_add_signed(offset),
+        };
+
+        self.pos = new_pos.ok_or(PmemError::InvalidSeek)?;
+        Ok(self.pos)
+    }
+
+    /// Truncates or extends the pool. Extended space is zeroed.
+    pub fn set_len(&mut self, size: u64) -> Result<(), PmemError> {
+        if !self.write {
+            return Err(PmemError::NotWritable);
+        }
+
+        let (addr, new_len, old_len) = MANAGER.lock().resize_pool_by_id(self.id, size)?;
+        self.remember(addr, new_len);
+
+        if new_len > old_len {
+            unsafe { zero(addr + old_len, new_len - old_len) };
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure all writes have reached persistent memory.
+    pub fn sync(&self) -> Result<(), PmemError> {
+        // writes are flushed from the caches right away, only the memory
+        // controllers' queues are left
+        self.len()?;
+        MANAGER.lock().flush_write_queues();
+        Ok(())
+    }
+
+    /// Returns the pool's contents as a slice.
+    ///
+    /// # Safety
+    ///
+    /// The slice becomes dangling as soon as the pool is resized, moved or
+    /// destroyed through another handle, and must not be aliased by another
+    /// mapping of the same pool. It must not be written unless the pool was
+    /// opened for writing, its pages may be read-only.
+    pub unsafe fn map(&mut self) -> Result<&mut [u8], PmemError> {
+        let (addr, len) = self.mapping()?;
+
+        Ok(slice::from_raw_parts_mut(addr as *mut u8, len as usize))
+    }
+
+    /// Returns the address and length of the pool, asking the manager only
+    /// if a pool was moved or destroyed since the last call.
+    fn mapping(&self) -> Result<(u64, u64), PmemError> {
+        if let Some((generation, addr, len)) = self.mapping.get() {
+            if generation == super::generation() {
+                return Ok((addr, len));
+            }
+        }
+
+        let generation = super::generation();
+        let (addr, len) = MANAGER.lock().get_pool_by_id(self.id, self.write)?;
+        self.mapping.set(Some((generation, addr, len)));
+        Ok((addr, len))
+    }
+
+    fn remember(&self, addr: u64, len: u64) {
+        self.mapping.set(Some((super::generation(), addr, len)));
+    }
+}
+
+/// Zeroes and persists `len` bytes of a mapped pool starting at `addr`.
+unsafe fn zero(addr: u64, len: u64) {
+    let buf = slice::from_raw_parts_mut(addr as *mut u8, len as usize);
+    buf.fill(0);
+    ll::persist_obj(buf, true);
+}
+
+impl ErrorType for PoolFile {
+    type Error = PmemError;
+}
+
+impl embedded_io::Read for PoolFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        PoolFile::read(self, buf)
+    }
+}
+
+impl embedded_io::Write for PoolFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        PoolFile::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.sync()
+    }
+}
+
+impl embedded_io::Seek for PoolFile {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        PoolFile::seek(self, pos)
+    }
+}
@@ -0,0 +1,414 @@
+//! A heap inside a pool for kernel code that needs some persistent memory,
+//! but not the transactions of Corundum.
+//!
+//! Allocations are identified by their offset in the pool, which stays the
+//! same across reboots while the pool's address doesn't. The heap's metadata
+//! is only changed through a redo log, so a crash leaves it as it was either
+//! before or after an operation. Memory that was allocated but not yet made
+//! reachable from the root object is lost in a crash, though.
+//!
+//! ```ignore
+//! let heap = PmemHeap::open("counters", 64 * 1024)?;
+//! let counters: Box<[u64; 16], &PmemHeap> = Box::new_in([0; 16], &heap);
+//! heap.set_root(Some(heap.offset(&*counters as *const _ as *const u8)));
+//! Box::leak(counters);
+//! ```
+
+use super::{table, PmemError, PoolId, MANAGER};
+use core::alloc::{AllocError, Allocator, Layout};
+use core::mem;
+use core::ptr::{self, NonNull};
+use corundum::ll;
+use spin::Mutex;
+use x86_64::structures::paging::PageSize;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"PMEMHEAP");
+/// Blocks start at and are multiples of this.
+const UNIT: u64 = 16;
+/// Every block starts with its size, followed by the offset of the next
+/// free block if it is free.
+const BLOCK_HEADER: u64 = 16;
+/// The smallest block that is split off as a free one.
+const MIN_BLOCK: u64 = BLOCK_HEADER + UNIT;
+/// Takes the place of the next free block in allocated blocks. It is odd,
+/// so no free block has it.
+const ALLOCATED: u64 = 0xa110_ca7e_d000_0001;
+const LOG_CAPACITY: usize = 8;
+/// Blocks start right after the header.
+const HEAP_START: u64 = (mem::size_of::<Header>() as u64 + UNIT - 1) & !(UNIT - 1);
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    /// The end of the heap.
+    len: u64,
+    /// The offset of the root object, 0 if there is none.
+    root: u64,
+    /// The offset of the first free block, 0 if there is none. Free blocks
+    /// are sorted by offset and never adjacent.
+    free: u64,
+    /// The number of writes in the log, which are applied again when the
+    /// heap is opened unless it is 0.
+    log_len: u64,
+    log: [LogEntry; LOG_CAPACITY],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct LogEntry {
+    offset: u64,
+    value: u64,
+}
+
+/// Writes to the metadata of the heap that take effect together.
+struct Transaction {
+    entries: [LogEntry; LOG_CAPACITY],
+    len: usize,
+}
+
+impl Transaction {
+    fn new() -> Self {
+        Transaction {
+            entries: [LogEntry::default(); LOG_CAPACITY],
+            len: 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u64) {
+        assert!(self.len < LOG_CAPACITY, "transaction too large");
+        self.entries[self.len] = LogEntry { offset, value };
+        self.len += 1;
+    }
+}
+
+/// Persistent memory of a single pool managed like a heap.
+///
+/// Besides [`allocate`] and [`deallocate`], which work with offsets, the
+/// heap implements [`Allocator`], so that collections can use it.
+///
+/// [`allocate`]: Self::allocate
+/// [`deallocate`]: Self::deallocate
+pub struct PmemHeap {
+    id: PoolId,
+    /// Where the pool is mapped.
+    base: u64,
+    inner: Mutex<Inner>,
+}
+
+impl PmemHeap {
+    /// Opens the heap in the pool `name`, which is created with `size`
+    /// bytes if it doesn't exist. A pool without a heap gets an empty one.
+    ///
+    /// The pool must not be resized or destroyed while the heap is open.
+    pub fn open(name: &str, size: u64) -> Result<Self, PmemError> {
+        let mut manager = MANAGER.lock();
+        let id = match manager.find_pool(name) {
+            Ok(id) => id,
+            Err(PmemError::NotFound) => {
+                manager.create_pool(name, size)?;
+                manager.find_pool(name)?
+            }
+            Err(err) => return Err(err),
+        };
+        let (base, len) = manager.get_pool_by_id(id, true)?;
+        drop(manager);
+
+        let inner = unsafe { Inner::open(base, len)? };
+        Ok(PmemHeap {
+            id,
+            base,
+            inner: Mutex::new(inner),
+        })
+    }
+
+    pub fn id(&self) -> PoolId {
+        self.id
+    }
+
+    /// Allocates memory for `layout` and returns its offset in the pool, or
+    /// `None` if the heap is full.
+    pub fn allocate(&self, layout: Layout) -> Option<u64> {
+        self.inner.lock().allocate(layout)
+    }
+
+    /// Frees the memory at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// The offset must have been returned by [`allocate`](Self::allocate) and
+    /// the memory must not be used afterwards.
+    pub unsafe fn deallocate(&self, offset: u64) {
+        self.inner.lock().deallocate(offset)
+    }
+
+    /// Returns the offset of the root object, through which the other
+    /// allocations are found again after a reboot.
+    pub fn root(&self) -> Option<u64> {
+        self.inner.lock().root()
+    }
+
+    pub fn set_root(&self, root: Option<u64>) {
+        self.inner.lock().set_root(root)
+    }
+
+    /// Returns the address of the memory at `offset`.
+    pub fn ptr(&self, offset: u64) -> *mut u8 {
+        (self.base + offset) as *mut u8
+    }
+
+    /// Returns the offset of `ptr`, which must point into the heap.
+    pub fn offset(&self, ptr: *const u8) -> u64 {
+        ptr as u64 - self.base
+    }
+}
+
+unsafe impl Allocator for PmemHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let offset = PmemHeap::allocate(self, layout).ok_or(AllocError)?;
+        let ptr = NonNull::new(self.ptr(offset)).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        PmemHeap::deallocate(self, self.offset(ptr.as_ptr()))
+    }
+}
+
+/// The heap at a mapped address.
+struct Inner {
+    base: u64,
+}
+
+impl Inner {
+    /// Opens the heap in the `len` bytes at `base`, creating an empty one if
+    /// there is none, and finishes an operation interrupted by a crash.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be persistent and not be used otherwise.
+    unsafe fn open(base: u64, len: u64) -> Result<Self, PmemError> {
+        if len < HEAP_START + MIN_BLOCK {
+            return Err(PmemError::InvalidHeap);
+        }
+
+        let mut inner = Inner { base };
+        let header = inner.header();
+        if header.magic != MAGIC {
+            inner.format(len);
+        } else if header.len > len || header.log_len > LOG_CAPACITY as u64 {
+            return Err(PmemError::InvalidHeap);
+        } else {
+            inner.apply_log();
+        }
+        Ok(inner)
+    }
+
+    /// Creates an empty heap with a single free block.
+    fn format(&mut self, len: u64) {
+        let end = len & !(UNIT - 1);
+        self.write(HEAP_START, end - HEAP_START);
+        self.write(HEAP_START + 8, 0);
+
+        let header = self.header();
+        header.len = end;
+        header.root = 0;
+        header.free = HEAP_START;
+        header.log_len = 0;
+        ll::persist_obj(header, true);
+
+        // the heap is only valid once everything else was written
+        header.magic = MAGIC;
+        ll::persist_obj(&header.magic, true);
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn header(&self) -> &mut Header {
+        unsafe { &mut *(self.base as *mut Header) }
+    }
+
+    fn read(&self, offset: u64) -> u64 {
+        unsafe { ptr::read((self.base + offset) as *const u64) }
+    }
+
+    fn write(&self, offset: u64, value: u64) {
+        let word = (self.base + offset) as *mut u64;
+        unsafe {
+            word.write(value);
+            ll::persist_obj(&*word, true);
+        }
+    }
+
+    /// Returns the offset of a field of the header.
+    fn field(&self, field: &u64) -> u64 {
+        field as *const u64 as u64 - self.base
+    }
+
+    /// Logs the writes of `tx` and applies them.
+    fn commit(&mut self, tx: Transaction) {
+        let header = self.header();
+        header.log[..tx.len].copy_from_slice(&tx.entries[..tx.len]);
+        ll::persist_obj(&header.log[..tx.len], true);
+        header.log_len = tx.len as u64;
+        ll::persist_obj(&header.log_len, true);
+
+        self.apply_log();
+    }
+
+    /// Applies the logged writes, which may have been applied before.
+    fn apply_log(&mut self) {
+        let header = self.header();
+        for entry in &header.log[..header.log_len as usize] {
+            self.write(entry.offset, entry.value);
+        }
+        header.log_len = 0;
+        ll::persist_obj(&header.log_len, true);
+    }
+
+    fn root(&self) -> Option<u64> {
+        Some(self.header().root).filter(|&root| root != 0)
+    }
+
+    fn set_root(&mut self, root: Option<u64>) {
+        let mut tx = Transaction::new();
+        tx.write(self.field(&self.header().root), root.unwrap_or(0));
+        self.commit(tx);
+    }
+
+    /// Takes the first free block that fits `layout`. What is left of it in
+    /// front and behind stays free.
+    fn allocate(&mut self, layout: Layout) -> Option<u64> {
+        if layout.align() as u64 > table::PageSize::SIZE {
+            // the pool is only page aligned
+            return None;
+        }
+        let size = (layout.size().max(1) as u64 + UNIT - 1) & !(UNIT - 1);
+        let align = (layout.align() as u64).max(UNIT);
+
+        let mut link = self.field(&self.header().free);
+        let mut block = self.read(link);
+        while block != 0 {
+            let block_end = block + self.read(block);
+            let next = self.read(block + 8);
+
+            if let Some((start, end)) = Self::fit(block, block_end, size, align) {
+                let mut tx = Transaction::new();
+                if start > block {
+                    tx.write(block, start - block);
+                    link = block + 8;
+                }
+                if end < block_end {
+                    tx.write(end, block_end - end);
+                    tx.write(end + 8, next);
+                    tx.write(link, end);
+                } else {
+                    tx.write(link, next);
+                }
+                tx.write(start, end - start);
+                tx.write(start + 8, ALLOCATED);
+                self.commit(tx);
+                return Some(start + BLOCK_HEADER);
+            }
+
+            link = block + 8;
+            block = next;
+        }
+        None
+    }
+
+    /// Returns where a block for `size` bytes aligned to `align` starts and
+    /// ends in the free block from `block` to `block_end`. A rest too small
+    /// for a free block is added to the allocation.
+    fn fit(block: u64, block_end: u64, size: u64, align: u64) -> Option<(u64, u64)> {
+        let align_up = |addr: u64| (addr + align - 1) & !(align - 1);
+
+        let mut data = align_up(block + BLOCK_HEADER);
+        if data - BLOCK_HEADER > block && data - BLOCK_HEADER - block < MIN_BLOCK {
+            data = align_up(block + MIN_BLOCK + BLOCK_HEADER);
+        }
+        let start = data - BLOCK_HEADER;
+        let mut end = data.checked_add(size)?;
+        if end > block_end {
+            return None;
+        }
+        if block_end - end < MIN_BLOCK {
+            end = block_end;
+        }
+        Some((start, end))
+    }
+
+    /// Frees the block of the allocation at `offset` and merges it with the
+    /// free blocks right before and after it.
+    fn deallocate(&mut self, offset: u64) {
+        let start = offset - BLOCK_HEADER;
+        assert_eq!(
+            self.read(start + 8),
+            ALLOCATED,
+            "freeing 0x{:x}, which isn't allocated",
+            offset
+        );
+        let end = start + self.read(start);
+
+        // find the free blocks around the freed one
+        let mut prev = 0;
+        let mut link = self.field(&self.header().free);
+        let mut next = self.read(link);
+        while next != 0 && next < start {
+            prev = next;
+            link = next + 8;
+            next = self.read(link);
+        }
+
+        let mut tx = Transaction::new();
+        let (next, next_size) = if next == end {
+            (self.read(next + 8), self.read(next))
+        } else {
+            (next, 0)
+        };
+        if prev != 0 && prev + self.read(prev) == start {
+            tx.write(prev, self.read(prev) + (end - start) + next_size);
+            tx.write(prev + 8, next);
+        } else {
+            tx.write(start, end - start + next_size);
+            tx.write(start + 8, next);
+            tx.write(link, start);
+        }
+        self.commit(tx);
+    }
+}
+
+#[test_case]
+fn heap_merges_blocks_and_replays_log() {
+    // the lib tests have no kernel heap
+    #[repr(align(4096))]
+    struct Pool([u8; 64 * 1024]);
+    static mut POOL: Pool = Pool([0; 64 * 1024]);
+
+    let base = unsafe { ptr::addr_of_mut!(POOL) } as u64;
+    let len = mem::size_of::<Pool>() as u64;
+    let mut inner = unsafe { Inner::open(base, len) }.unwrap();
+    let free = inner.read(HEAP_START);
+
+    let a = inner.allocate(Layout::new::<u64>()).unwrap();
+    let b = inner
+        .allocate(Layout::from_size_align(100, 256).unwrap())
+        .unwrap();
+    let c = inner.allocate(Layout::new::<[u64; 4]>()).unwrap();
+    assert_eq!(b % 256, 0);
+    assert!(a < b && b < c);
+    inner.deallocate(a);
+    inner.deallocate(c);
+    inner.deallocate(b);
+    assert_eq!(inner.header().free, HEAP_START);
+    assert_eq!(inner.read(HEAP_START), free);
+
+    // a crash after committing leaves the writes in the log
+    let header = inner.header();
+    header.log[0] = LogEntry {
+        offset: inner.field(&header.root),
+        value: a,
+    };
+    header.log_len = 1;
+    let inner = unsafe { Inner::open(base, len) }.unwrap();
+    assert_eq!(inner.root(), Some(a));
+    assert_eq!(inner.header().log_len, 0);
+}
@@ -1,8 +1,8 @@
-use super::NfitDevice;
-use crate::vmem::ReserveRegion;
-use alloc::collections::BTreeMap;
+use super::{NfitDevice, PmemError};
+use crate::vmem::{Fit, RegionAllocator};
 use alloc::vec::Vec;
 use core::ffi::CStr;
+use core::iter;
 use core::mem;
 use core::ops;
 use core::str;
@@ -17,7 +17,7 @@ pub type PageSize = Size4KiB;
 
 const ENTRY_SPACE: usize = PageSize::SIZE as usize - 2;
 const ENTRY_COUNT: usize = ENTRY_SPACE / mem::size_of::<Entry>();
-const NAME_LEN: usize = 30;
+pub const NAME_LEN: usize = 30;
 
 const _: () = assert!(
     mem::size_of::<Inner>() as u64 == PageSize::SIZE,
@@ -26,18 +26,21 @@ const _: () = assert!(
 
 pub struct Table {
     inner: &'static mut Inner,
-    free_regions: BTreeMap<u64, u64>,
+    free_regions: RegionAllocator,
 }
 
 impl Table {
+    /// Reads the table of a device, or writes an empty one. Fails if the
+    /// kernel heap is exhausted.
+    ///
     /// # Safety
     ///
     /// Caller must ensure that there are no other references made from the
     /// passed address.
-    pub unsafe fn new(device: &NfitDevice, pages: PageRange<PageSize>) -> Self {
+    pub unsafe fn new(device: &NfitDevice, pages: PageRange<PageSize>) -> Result<Self, PmemError> {
         let address = pages.start.start_address().as_u64();
         let inner = Inner::new(address);
-        let free_regions: BTreeMap<u64, u64>;
+        let free_regions;
 
         trace!(
             "Validate pmem table at 0x{:012x} (size: {} MiB)",
@@ -46,24 +49,28 @@ impl Table {
         );
 
         if inner.is_valid() {
-            let mut taken: Vec<_> = inner
-                .entries()
-                .into_iter()
-                .inspect(|entry| {
-                    trace!(
-                        "Found pool '{}' at offset 0x{:012x} (size: {} MiB, real size: {} MiB)",
-                        entry.name(),
-                        entry.offset(),
-                        entry.len() as f64 / 1024_f64 / 1024_f64,
-                        entry.real_len() as f64 / 1024_f64 / 1024_f64,
-                    )
-                })
-                .map(|entry| entry.offset()..(entry.offset() + entry.real_len()))
-                .collect();
+            let mut taken = Vec::new();
+            taken.try_reserve_exact(ENTRY_COUNT)?;
+            taken.extend(
+                inner
+                    .entries()
+                    .into_iter()
+                    .inspect(|entry| {
+                        trace!(
+                            "Found pool '{}' at offset 0x{:012x} (size: {} MiB, real size: {} MiB)",
+                            entry.name(),
+                            entry.offset(),
+                            entry.len() as f64 / 1024_f64 / 1024_f64,
+                            entry.real_len() as f64 / 1024_f64 / 1024_f64,
+                        )
+                    })
+                    .map(|entry| entry.offset()..(entry.offset() + entry.real_len())),
+            );
 
             taken.sort_unstable_by(|a, b| a.start.cmp(&b.start));
 
             let mut usable = Vec::new();
+            usable.try_reserve_exact(taken.len() + 1)?;
             let mut current = PageSize::SIZE;
 
             for region in taken.into_iter() {
@@ -75,35 +82,36 @@ impl Table {
                 usable.push(current..device.size);
             }
 
-            free_regions = usable
-                .into_iter()
-                .map(|r| (r.end - r.start, r.start))
-                .filter(|(size, _)| *size > 0)
-                .collect();
+            free_regions = RegionAllocator::with_regions(Fit::Best, usable);
         } else {
             trace!("Write empty table");
 
             inner.init();
-            free_regions = [(device.size - PageSize::SIZE, PageSize::SIZE)]
-                .into_iter()
-                .filter(|(size, _)| *size > 0)
-                .collect();
+            free_regions =
+                RegionAllocator::with_regions(Fit::Best, iter::once(PageSize::SIZE..device.size));
         }
 
-        Table {
+        Ok(Table {
             inner,
             free_regions,
-        }
+        })
     }
 
-    pub fn allocate(&mut self, name: &str, size: u64) -> Option<u64> {
-        let needed_size = size.max(PageSize::SIZE);
-        if name.len() > NAME_LEN || self.inner.entries().into_iter().count() == ENTRY_COUNT {
-            return None;
+    pub fn allocate(&mut self, name: &str, size: u64) -> Result<u64, PmemError> {
+        let needed_size = real_len(size)?;
+        check_name(name)?;
+        if self.inner.entries().into_iter().count() == ENTRY_COUNT {
+            return Err(PmemError::TableFull);
         }
 
-        let r = self.reserve_range(needed_size, PageSize::SIZE)?;
-        let index = self.inner.insert(name, r.start, size)?;
+        let r = self
+            .free_regions
+            .reserve(needed_size, PageSize::SIZE)
+            .ok_or(PmemError::NoSpace)?;
+        let index = self
+            .inner
+            .insert(name, r.start, size)
+            .ok_or(PmemError::TableFull)?;
 
         trace!(
             "Added table entry #{} '{}' (0x{:x}-0x{:x})",
@@ -113,13 +121,11 @@ impl Table {
             r.end - 1,
         );
 
-        Some(r.start)
+        Ok(r.start)
     }
 
-    pub fn deallocate(&mut self, index: usize) -> bool {
-        let Some(entry) = self.inner.entries.get(index) else {
-            return false;
-        };
+    pub fn deallocate(&mut self, index: usize) -> Result<(), PmemError> {
+        let entry = self.get(index).ok_or(PmemError::NotFound)?;
 
         let offset = entry.offset();
         let len = entry.real_len();
@@ -133,33 +139,31 @@ impl Table {
             r.end - 1,
         );
 
-        if self.release_range(r) {
-            self.inner.remove(index)
-        } else {
-            false
-        }
+        self.free_regions.release(r);
+        self.inner.remove(index);
+        Ok(())
     }
 
-    pub fn reallocate(&mut self, index: usize, new_size: u64) -> bool {
-        let needed_size = new_size.max(PageSize::SIZE);
-        let Some(entry) = self.inner.entries.get(index) else {
-            return false;
-        };
+    /// Moves a pool to a region that fits `new_size` bytes.
+    ///
+    /// The pool's contents are not copied.
+    pub fn reallocate(&mut self, index: usize, new_size: u64) -> Result<(), PmemError> {
+        let needed_size = real_len(new_size)?;
+        let entry = self.get(index).ok_or(PmemError::NotFound)?;
         if entry.real_len() >= needed_size {
-            return false;
+            return self.resize(index, new_size);
         }
 
         let old_range = entry.offset..(entry.offset + entry.real_len());
-        let Some(new_range) = self.reserve_range(needed_size, PageSize::SIZE) else {
-            return false;
-        };
-        self.release_range(old_range.clone());
-
-        let Some(entry) = self.inner.entries.get_mut(index) else {
-            return false;
-        };
+        let new_range = self
+            .free_regions
+            .reserve(needed_size, PageSize::SIZE)
+            .ok_or(PmemError::NoSpace)?;
+        self.free_regions.release(old_range.clone());
+
+        let entry = self.get_mut(index).ok_or(PmemError::NotFound)?;
         entry.offset = new_range.start;
-        entry.length = needed_size;
+        entry.length = new_size;
 
         trace!(
             "Moved region of #{} '{}' from 0x{:x}-0x{:x} to 0x{:x}-0x{:x}",
@@ -171,9 +175,59 @@ impl Table {
             new_range.end - 1,
         );
 
-        ll::persist_obj(self, true);
+        ll::persist_obj(entry, true);
 
-        true
+        Ok(())
+    }
+
+    /// Changes the length of a pool without moving it.
+    ///
+    /// Fails if the new length doesn't fit into the pages already reserved
+    /// for the pool. Pages that are no longer needed are given back.
+    pub fn resize(&mut self, index: usize, new_size: u64) -> Result<(), PmemError> {
+        let new_real_len = real_len(new_size)?;
+        let entry = self.get_mut(index).ok_or(PmemError::NotFound)?;
+
+        let offset = entry.offset();
+        let old_real_len = entry.real_len();
+        if new_real_len > old_real_len {
+            return Err(PmemError::NoSpace);
+        }
+
+        entry.length = new_size;
+        ll::persist_obj(entry, true);
+
+        trace!(
+            "Resized table entry #{} '{}' to 0x{:x} bytes",
+            index,
+            entry.name(),
+            new_size,
+        );
+
+        if new_real_len < old_real_len {
+            self.free_regions
+                .release((offset + new_real_len)..(offset + old_real_len));
+        }
+
+        Ok(())
+    }
+
+    pub fn rename(&mut self, index: usize, new_name: &str) -> Result<(), PmemError> {
+        check_name(new_name)?;
+        let entry = self.get_mut(index).ok_or(PmemError::NotFound)?;
+
+        trace!(
+            "Renaming table entry #{} '{}' to '{}'",
+            index,
+            entry.name(),
+            new_name,
+        );
+
+        entry.name.fill(0);
+        entry.name[..new_name.len()].copy_from_slice(new_name.as_bytes());
+
+        ll::persist_obj(entry, true);
+        Ok(())
     }
 
     pub fn entries(&self) -> impl IntoIterator<Item = IterEntry> {
@@ -181,20 +235,39 @@ impl Table {
     }
 
     pub fn get(&self, index: usize) -> Option<&Entry> {
-        self.inner.entries.get(index)
+        self.inner
+            .entries
+            .get(index)
+            .filter(|entry| !entry.name().is_empty())
     }
 
     pub fn get_mut(&mut self, index: usize) -> Option<&mut Entry> {
-        self.inner.entries.get_mut(index)
+        self.inner
+            .entries
+            .get_mut(index)
+            .filter(|entry| !entry.name().is_empty())
     }
 }
 
-impl ReserveRegion for Table {
-    fn free_regions(&mut self) -> &mut BTreeMap<u64, u64> {
-        &mut self.free_regions
+/// Checks that a name can be stored in a table entry.
+pub fn check_name(name: &str) -> Result<(), PmemError> {
+    if name.is_empty() {
+        Err(PmemError::InvalidName)
+    } else if name.len() > NAME_LEN {
+        Err(PmemError::NameTooLong)
+    } else {
+        Ok(())
     }
 }
 
+/// Returns the number of bytes reserved for a pool of `len` bytes.
+fn real_len(len: u64) -> Result<u64, PmemError> {
+    len.max(1)
+        .checked_add(PageSize::SIZE - 1)
+        .map(|len| len & !(PageSize::SIZE - 1))
+        .ok_or(PmemError::TooLarge)
+}
+
 #[repr(C, packed)]
 struct Inner {
     magic_number: u16,
@@ -252,8 +325,8 @@ impl Inner {
         self.entries
             .as_slice()
             .iter()
-            .filter(|entry| !entry.name().is_empty())
             .enumerate()
+            .filter(|(_, entry)| !entry.name().is_empty())
             .map(|(i, e)| IterEntry { index: i, inner: e })
     }
 }
@@ -283,7 +356,7 @@ impl Entry {
     }
 
     pub fn real_len(&self) -> u64 {
-        x86_64::align_up(self.len().max(1), PageSize::SIZE)
+        real_len(self.len()).unwrap()
     }
 
     pub fn frames(&self) -> u64 {
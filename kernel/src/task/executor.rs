@@ -1,29 +1,81 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::join::JoinHandle;
+use super::{RawTask, Task, TaskId};
+use crate::allocator::tracking;
+use alloc::{sync::Arc, task::Wake, vec::Vec};
+use core::alloc::AllocError;
+use core::fmt;
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
 
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
+    /// Sorted by id, each task with its waker. Kept in a `Vec` and the
+    /// wakers made when spawning, so that running out of memory fails the
+    /// spawn instead of panicking later.
+    tasks: Vec<(RawTask, Waker)>,
     task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+/// Why a task couldn't be spawned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    OutOfMemory,
+    /// More tasks are ready to run than the queue holds.
+    QueueFull,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::OutOfMemory => "out of memory",
+            Self::QueueFull => "task queue full",
+        })
+    }
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
-            tasks: BTreeMap::new(),
+            tasks: Vec::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
+    /// Spawns a task and returns the handle to its output. Dropping the
+    /// handle leaves the task running.
+    pub fn spawn<T: 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        match self.insert(task) {
+            Ok(handle) => handle,
+            Err(err) => panic!("spawning task failed: {}", err),
+        }
+    }
+
+    /// Like [`spawn`](Self::spawn), but fails instead of panicking if the
+    /// kernel heap is exhausted or too many tasks are queued.
+    pub fn try_spawn<T: 'static>(&mut self, task: Task<T>) -> Result<JoinHandle<T>, SpawnError> {
+        self.insert(task)
+    }
+
+    fn insert<T: 'static>(&mut self, task: Task<T>) -> Result<JoinHandle<T>, SpawnError> {
+        let _site = tracking::site("executor");
+        let (task, handle) = task.into_parts();
         let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
+        let i = match self
+            .tasks
+            .binary_search_by_key(&task_id, |(task, _)| task.id)
+        {
+            Ok(_) => panic!("task with same ID already in tasks"),
+            Err(i) => i,
+        };
+        self.tasks
+            .try_reserve(1)
+            .map_err(|_| SpawnError::OutOfMemory)?;
+        let waker = TaskWaker::try_new(task_id, self.task_queue.clone())
+            .map_err(|_| SpawnError::OutOfMemory)?;
+        if self.task_queue.push(task_id).is_err() {
+            return Err(SpawnError::QueueFull);
         }
-        self.task_queue.push(task_id).expect("queue full");
+        self.tasks.insert(i, (task, waker));
+        Ok(handle)
     }
 
     pub fn run(&mut self) -> ! {
@@ -34,27 +86,20 @@ impl Executor {
     }
 
     fn run_ready_tasks(&mut self) {
-        // destructure `self` to avoid borrow checker errors
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-        } = self;
-
-        while let Ok(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
+        while let Ok(task_id) = self.task_queue.pop() {
+            let i = match self
+                .tasks
+                .binary_search_by_key(&task_id, |(task, _)| task.id)
+            {
+                Ok(i) => i,
+                Err(_) => continue, // task no longer exists
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let (task, waker) = &mut self.tasks[i];
             let mut context = Context::from_waker(waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+                    // task done -> remove it and its waker
+                    self.tasks.remove(i);
                 }
                 Poll::Pending => {}
             }
@@ -79,11 +124,11 @@ struct TaskWaker {
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+    fn try_new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Result<Waker, AllocError> {
+        Ok(Waker::from(Arc::try_new(TaskWaker {
             task_id,
             task_queue,
-        }))
+        })?))
     }
 
     fn wake_task(&self) {
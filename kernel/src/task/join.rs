@@ -0,0 +1,157 @@
+//! Handles through which spawned tasks pass on their output.
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// The state of a task shared with its [`JoinHandle`]. Tasks never leave
+/// the executor they were spawned on, so it isn't locked.
+pub(super) struct Shared<T> {
+    state: RefCell<State<T>>,
+}
+
+struct State<T> {
+    output: Option<T>,
+    finished: bool,
+    aborted: bool,
+    /// Wakes the task, so that it notices it was aborted.
+    task: Option<Waker>,
+    /// Wakes the task awaiting the handle.
+    join: Option<Waker>,
+}
+
+impl<T> Shared<T> {
+    pub(super) fn new() -> Self {
+        Shared {
+            state: RefCell::new(State {
+                output: None,
+                finished: false,
+                aborted: false,
+                task: None,
+                join: None,
+            }),
+        }
+    }
+}
+
+/// Runs a future and stores its output for the handle.
+pub(super) struct Joinable<F: Future> {
+    pub(super) future: F,
+    pub(super) shared: Rc<Shared<F::Output>>,
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // the future is never moved out
+        let this = unsafe { self.get_unchecked_mut() };
+        {
+            let mut state = this.shared.state.borrow_mut();
+            if state.aborted {
+                state.finished = true;
+                let join = state.join.take();
+                drop(state);
+                if let Some(waker) = join {
+                    waker.wake();
+                }
+                return Poll::Ready(());
+            }
+            if !state.task.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                state.task = Some(cx.waker().clone());
+            }
+        }
+
+        // not locked, as the future may use its own handle
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let output = match future.poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let join = {
+            let mut state = this.shared.state.borrow_mut();
+            state.output = Some(output);
+            state.finished = true;
+            state.task = None;
+            state.join.take()
+        };
+        if let Some(waker) = join {
+            waker.wake();
+        }
+        Poll::Ready(())
+    }
+}
+
+/// Why a task has no output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted before it finished.
+    Aborted,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Aborted => "task aborted",
+        })
+    }
+}
+
+/// Completes with the output of a spawned task. Dropping the handle detaches
+/// the task, which keeps running.
+pub struct JoinHandle<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn new(shared: Rc<Shared<T>>) -> Self {
+        JoinHandle { shared }
+    }
+
+    /// Returns whether the task finished, or stopped after being aborted.
+    pub fn is_finished(&self) -> bool {
+        self.shared.state.borrow().finished
+    }
+
+    /// Stops the task the next time the executor gets to it, dropping its
+    /// future. Has no effect if the task finished already.
+    pub fn abort(&self) {
+        let (task, join) = {
+            let mut state = self.shared.state.borrow_mut();
+            if state.finished {
+                return;
+            }
+            state.aborted = true;
+            (state.task.take(), state.join.take())
+        };
+        if let Some(waker) = task {
+            waker.wake();
+        }
+        // the handle may be awaited at the same time, like in a select
+        if let Some(waker) = join {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.shared.state.borrow_mut();
+        if let Some(output) = state.output.take() {
+            return Poll::Ready(Ok(output));
+        }
+        if state.aborted {
+            return Poll::Ready(Err(JoinError::Aborted));
+        }
+        assert!(!state.finished, "JoinHandle polled after completion");
+
+        state.join = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -1,5 +1,7 @@
 use alloc::boxed::Box;
+use alloc::rc::Rc;
 use core::{
+    alloc::AllocError,
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
@@ -7,22 +9,66 @@ use core::{
 };
 
 pub mod executor;
+pub mod join;
 pub mod keyboard;
 pub mod simple_executor;
+pub mod timer;
 
-pub struct Task {
-    id: TaskId,
-    future: Pin<Box<dyn Future<Output = ()>>>,
+use join::{JoinHandle, Joinable, Shared};
+
+/// A future to be spawned on an executor, with the handle its output is
+/// passed to.
+pub struct Task<T = ()> {
+    raw: RawTask,
+    handle: JoinHandle<T>,
 }
 
-impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+impl<T: 'static> Task<T> {
+    pub fn new(future: impl Future<Output = T> + 'static) -> Task<T> {
+        let shared = Rc::new(Shared::new());
+        let future = Joinable {
+            future,
+            shared: shared.clone(),
+        };
         Task {
-            id: TaskId::new(),
-            future: Box::pin(future),
+            raw: RawTask {
+                id: TaskId::new(),
+                future: Box::pin(future),
+            },
+            handle: JoinHandle::new(shared),
         }
     }
 
+    /// Like [`new`](Self::new), but fails if the future can't be moved to
+    /// the heap.
+    pub fn try_new(future: impl Future<Output = T> + 'static) -> Result<Task<T>, AllocError> {
+        let shared = Rc::try_new(Shared::new())?;
+        let future = Box::try_new(Joinable {
+            future,
+            shared: shared.clone(),
+        })?;
+        Ok(Task {
+            raw: RawTask {
+                id: TaskId::new(),
+                future: Box::into_pin(future),
+            },
+            handle: JoinHandle::new(shared),
+        })
+    }
+
+    /// Splits the task into the part executors run and its handle.
+    fn into_parts(self) -> (RawTask, JoinHandle<T>) {
+        (self.raw, self.handle)
+    }
+}
+
+/// A task with its output type erased, as executors keep it.
+struct RawTask {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl RawTask {
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
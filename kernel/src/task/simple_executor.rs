@@ -1,9 +1,10 @@
-use super::Task;
+use super::join::JoinHandle;
+use super::{RawTask, Task};
 use alloc::collections::VecDeque;
 use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 
 pub struct SimpleExecutor {
-    task_queue: VecDeque<Task>,
+    task_queue: VecDeque<RawTask>,
 }
 
 impl SimpleExecutor {
@@ -13,8 +14,10 @@ impl SimpleExecutor {
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        self.task_queue.push_back(task)
+    pub fn spawn<T: 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.task_queue.push_back(task);
+        handle
     }
 
     pub fn run(&mut self) {
@@ -0,0 +1,276 @@
+//! Time for async tasks, counted in ticks of the programmable interval timer.
+//!
+//! Tasks waiting for a tick are kept in a timer wheel, a slot per tick
+//! modulo its size. The timer interrupt only looks at the slot of the
+//! current tick and wakes the tasks whose deadline has come.
+
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use futures_util::stream::{Stream, StreamExt};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+pub const TICKS_PER_SECOND: u64 = 1000;
+/// The frequency the PIT divides down to [`TICKS_PER_SECOND`].
+const PIT_FREQUENCY: u64 = 1_193_182;
+const SLOTS: usize = 64;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Only locked with interrupts disabled, as the timer interrupt locks it.
+static WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+
+/// Programs channel 0 of the PIT to interrupt [`TICKS_PER_SECOND`] times a
+/// second.
+pub(crate) fn init() {
+    let divisor = (PIT_FREQUENCY / TICKS_PER_SECOND) as u16;
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel0 = Port::<u8>::new(0x40);
+    unsafe {
+        // channel 0, low then high byte, rate generator
+        command.write(0x34);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Called by the timer interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    WHEEL.lock().expire(now);
+}
+
+/// Returns the ticks since the timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since the timer was started.
+pub fn uptime() -> Duration {
+    let ticks = ticks();
+    Duration::from_secs(ticks / TICKS_PER_SECOND)
+        + Duration::from_nanos(ticks % TICKS_PER_SECOND * 1_000_000_000 / TICKS_PER_SECOND)
+}
+
+/// Returns the ticks covering `duration`, rounded up.
+fn to_ticks(duration: Duration) -> u64 {
+    let ticks = (duration.as_nanos() * TICKS_PER_SECOND as u128 + 999_999_999) / 1_000_000_000;
+    ticks.try_into().unwrap_or(u64::MAX)
+}
+
+struct Entry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+}
+
+struct Wheel {
+    slots: [Vec<Entry>; SLOTS],
+    next_id: u64,
+}
+
+impl Wheel {
+    const fn new() -> Self {
+        const EMPTY: Vec<Entry> = Vec::new();
+        Wheel {
+            slots: [EMPTY; SLOTS],
+            next_id: 0,
+        }
+    }
+
+    fn slot(&mut self, deadline: u64) -> &mut Vec<Entry> {
+        &mut self.slots[deadline as usize % SLOTS]
+    }
+
+    fn insert(&mut self, deadline: u64, waker: Waker) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.slot(deadline).push(Entry {
+            id,
+            deadline,
+            waker,
+        });
+        id
+    }
+
+    /// Replaces the waker of an entry, which is inserted again if it expired.
+    fn update(&mut self, id: u64, deadline: u64, waker: &Waker) {
+        let slot = self.slot(deadline);
+        match slot.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) if !entry.waker.will_wake(waker) => entry.waker = waker.clone(),
+            Some(_) => {}
+            None => slot.push(Entry {
+                id,
+                deadline,
+                waker: waker.clone(),
+            }),
+        }
+    }
+
+    fn remove(&mut self, id: u64, deadline: u64) {
+        let slot = self.slot(deadline);
+        if let Some(i) = slot.iter().position(|entry| entry.id == id) {
+            slot.swap_remove(i);
+        }
+    }
+
+    /// Wakes the entries whose deadline is `now`. Entries of later rounds of
+    /// the wheel stay. The slots keep their capacity, so nothing is freed.
+    fn expire(&mut self, now: u64) {
+        let slot = self.slot(now);
+        let mut i = 0;
+        while i < slot.len() {
+            if slot[i].deadline <= now {
+                slot.swap_remove(i).waker.wake();
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Completes once a tick has come, created by [`sleep`] and
+/// [`sleep_until`].
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: u64,
+    /// The entry in the wheel, once it was polled.
+    id: Option<u64>,
+}
+
+/// Waits for at least `duration`, rounded up to whole ticks.
+pub fn sleep(duration: Duration) -> Sleep {
+    // the current tick may be about to end
+    sleep_until(ticks().saturating_add(to_ticks(duration)).saturating_add(1))
+}
+
+/// Waits until [`ticks`] has reached `deadline`.
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep { deadline, id: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    /// Waits for another deadline, as if created anew.
+    pub fn reset(&mut self, deadline: u64) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(id) = self.id.take() {
+            interrupts::without_interrupts(|| WHEEL.lock().remove(id, self.deadline));
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        interrupts::without_interrupts(|| {
+            let mut wheel = WHEEL.lock();
+            // checked with the wheel locked, so the tick can't pass unnoticed
+            if ticks() >= this.deadline {
+                if let Some(id) = this.id.take() {
+                    wheel.remove(id, this.deadline);
+                }
+                return Poll::Ready(());
+            }
+
+            match this.id {
+                Some(id) => wheel.update(id, this.deadline, cx.waker()),
+                None => this.id = Some(wheel.insert(this.deadline, cx.waker().clone())),
+            }
+            Poll::Pending
+        })
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Yields the tick of each period, created by [`interval`]. Periods that
+/// were missed because the task didn't poll in time are skipped.
+#[derive(Debug)]
+pub struct Interval {
+    period: u64,
+    sleep: Sleep,
+}
+
+/// Yields right away and then every `period`, rounded up to whole ticks.
+pub fn interval(period: Duration) -> Interval {
+    Interval {
+        period: to_ticks(period).max(1),
+        sleep: sleep_until(ticks()),
+    }
+}
+
+impl Interval {
+    /// Waits for the next period and returns its tick.
+    pub async fn tick(&mut self) -> u64 {
+        self.next().await.expect("intervals never end")
+    }
+}
+
+impl Stream for Interval {
+    type Item = u64;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u64>> {
+        let this = self.get_mut();
+        if Pin::new(&mut this.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let tick = this.sleep.deadline();
+        let missed = (ticks() - tick) / this.period;
+        this.sleep.reset(tick + (missed + 1) * this.period);
+        Poll::Ready(Some(tick))
+    }
+}
+
+/// The error of a [`timeout`] that ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Runs a future for at most a duration, created by [`timeout`].
+#[derive(Debug)]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Cancels `future` if it doesn't complete within `duration`.
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // the future is never moved out, and `Sleep` is `Unpin`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
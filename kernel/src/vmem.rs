@@ -1,40 +1,60 @@
-use crate::memory::SimpleFrameAllocator;
+mod allocation;
+mod attributes;
+mod region;
+mod space;
+mod walk;
+
+pub use allocation::{Allocation, Owner};
+pub use attributes::{init as init_attributes, Attributes, CacheType};
+pub use region::{Fit, RegionAllocator};
+pub use space::AddressSpace;
+pub use walk::{
+    get_mappings, MappedRegion, MappedRegions, Mappings, PageTableWalker, Pages, PhysFrames,
+    UsableRegions, VirtMapping,
+};
+
+use crate::memory::BitmapFrameAllocator;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
-use alloc::{collections::BTreeMap, vec};
 use core::cell::OnceCell;
-use core::cmp::Ordering;
 use core::fmt;
 use core::ops::{DerefMut, Range};
 use spin::Mutex;
+use x86_64::instructions::tlb;
+use x86_64::structures::paging::mapper::{FlagUpdateError, Translate, TranslateResult};
 use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::page_table::PageTableEntry;
 use x86_64::structures::paging::Page;
 use x86_64::structures::paging::PageTableFlags as Flags;
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, PageSize, PageTable, PageTableFlags, PhysFrame,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, PageSize, PageTable, PhysFrame,
         Size1GiB, Size2MiB, Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
 
-pub static MANAGER: Mutex<OnceCell<Manager<SimpleFrameAllocator>>> = Mutex::new(OnceCell::new());
+pub static MANAGER: Mutex<OnceCell<Manager<BitmapFrameAllocator>>> = Mutex::new(OnceCell::new());
 
 #[derive(Debug)]
 pub struct Manager<'a, A> {
     mapper: OffsetPageTable<'a>,
     frame_allocator: &'a Mutex<A>,
-    free_regions: BTreeMap<u64, u64>,
-}
-
-impl<'a, A> ReserveRegion for Manager<'a, A> {
-    fn free_regions(&mut self) -> &mut BTreeMap<u64, u64> {
-        &mut self.free_regions
-    }
+    free_regions: RegionAllocator,
+    /// Allocations by the start of their reserved range.
+    allocations: BTreeMap<u64, Allocation>,
+    /// Page tables allocated while mapping. Only these are freed once they
+    /// are empty, the bootloader's tables may lie outside the frame
+    /// allocator's memory.
+    tables: BTreeSet<PhysFrame>,
+    /// Where the lower half comes from, if these are the tables of an
+    /// address space.
+    kernel_half: Option<space::KernelHalf>,
 }
 
 impl<'a, A> Manager<'a, A>
 where
-    A: FrameAllocator<Size4KiB>,
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
 {
     pub fn new(
         mapper: OffsetPageTable<'a>,
@@ -44,10 +64,10 @@ where
         Manager {
             mapper,
             frame_allocator,
-            free_regions: usable_regions
-                .into_iter()
-                .map(|r| (r.end - r.start, r.start))
-                .collect(),
+            free_regions: RegionAllocator::with_regions(Fit::Best, usable_regions),
+            allocations: BTreeMap::new(),
+            tables: BTreeSet::new(),
+            kernel_half: None,
         }
     }
 
@@ -59,30 +79,251 @@ where
         self.mapper.level_4_table() as *const PageTable as u64
     }
 
-    pub fn allocate<S>(&mut self, phys_start: PhysAddr, page_count: u64) -> Option<PageRange<S>>
+    pub fn allocate<S>(
+        &mut self,
+        phys_start: PhysAddr,
+        page_count: u64,
+        attributes: Attributes,
+        owner: Owner,
+    ) -> Option<PageRange<S>>
     where
         S: PageSize + fmt::Debug,
         OffsetPageTable<'a>: Mapper<S>,
     {
-        self.reserve_page_range(page_count.max(1)).map(|r| {
-            self.map_page_range(r, phys_start);
-            r
-        })
+        self.allocate_with_guard(phys_start, page_count, attributes, owner, 0)
+    }
+
+    /// Like [`Manager::allocate`], but leaves an unmapped page before and
+    /// after the mapping, so that running over either end faults.
+    pub fn allocate_guarded<S>(
+        &mut self,
+        phys_start: PhysAddr,
+        page_count: u64,
+        attributes: Attributes,
+        owner: Owner,
+    ) -> Option<PageRange<S>>
+    where
+        S: PageSize + fmt::Debug,
+        OffsetPageTable<'a>: Mapper<S>,
+    {
+        self.allocate_with_guard(phys_start, page_count, attributes, owner, S::SIZE)
+    }
+
+    fn allocate_with_guard<S>(
+        &mut self,
+        phys_start: PhysAddr,
+        page_count: u64,
+        attributes: Attributes,
+        owner: Owner,
+        guard: u64,
+    ) -> Option<PageRange<S>>
+    where
+        S: PageSize + fmt::Debug,
+        OffsetPageTable<'a>: Mapper<S>,
+    {
+        let (reserved, pages) = self.reserve_page_range(page_count.max(1), guard)?;
+        self.map_page_range(pages, phys_start, attributes);
+
+        let mapped = pages.start.start_address().as_u64()..pages.end.start_address().as_u64();
+        self.allocations.insert(
+            reserved.start,
+            Allocation {
+                reserved,
+                mapped,
+                owner,
+                owns_frames: false,
+            },
+        );
+        Some(pages)
+    }
+
+    /// Maps `page_count` pages to zeroed frames from the frame allocator,
+    /// which are given back once the pages are deallocated.
+    pub fn allocate_anonymous(
+        &mut self,
+        page_count: u64,
+        attributes: Attributes,
+        owner: Owner,
+    ) -> Option<PageRange<Size4KiB>> {
+        let (reserved, pages) = self.reserve_page_range::<Size4KiB>(page_count.max(1), 0)?;
+
+        for page in pages {
+            if !self.map_anonymous_page(page, attributes) {
+                self.unmap_page_range(Page::range(pages.start, page), true);
+                self.free_regions.release(reserved);
+                return None;
+            }
+        }
+
+        let mapped = pages.start.start_address().as_u64()..pages.end.start_address().as_u64();
+        self.allocations.insert(
+            reserved.start,
+            Allocation {
+                reserved,
+                mapped,
+                owner,
+                owns_frames: true,
+            },
+        );
+        Some(pages)
     }
 
-    fn reserve_page_range<S: PageSize>(&mut self, page_count: u64) -> Option<PageRange<S>> {
-        let needed_size = x86_64::align_up(page_count * S::SIZE, S::SIZE);
+    /// Maps a page to a zeroed frame from the frame allocator.
+    fn map_anonymous_page(&mut self, page: Page, attributes: Attributes) -> bool {
+        let mut frame_allocator = self.frame_allocator.lock();
+        let Some(frame) = frame_allocator.allocate_frame() else {
+            return false;
+        };
+
+        let ptr: *mut u8 =
+            (self.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+        unsafe { ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+
+        let mut table_frames = TableFrames::new(frame_allocator.deref_mut());
+        let res = unsafe {
+            self.mapper.map_to_with_table_flags(
+                page,
+                frame,
+                attributes.flags(),
+                Flags::PRESENT | Flags::WRITABLE,
+                &mut table_frames,
+            )
+        };
+        let tables = table_frames.allocated;
+        let mapped = match res {
+            Ok(flush) => {
+                flush.flush();
+                true
+            }
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                false
+            }
+        };
+
+        drop(frame_allocator);
+        if let Some(table) = tables[0] {
+            self.note_new_table(page.start_address(), table);
+        }
+        self.tables.extend(tables.into_iter().flatten());
+        mapped
+    }
+
+    /// Records the owner of a range that was mapped or reserved without the
+    /// manager, like the framebuffer or the demand-paged heap.
+    pub fn register(&mut self, range: Range<VirtAddr>, owner: Owner) {
+        let range = range.start.as_u64()..range.end.as_u64();
+        // ranges that are reserved but not mapped yet still look free
+        self.free_regions.take(range.clone());
+        self.allocations.insert(
+            range.start,
+            Allocation {
+                reserved: range.clone(),
+                mapped: range,
+                owner,
+                owns_frames: false,
+            },
+        );
+    }
 
-        self.reserve_range(needed_size, S::SIZE).map(|r| {
-            let addr = VirtAddr::new(r.start);
+    /// Changes the owner of the allocation containing `addr`.
+    pub fn set_owner(&mut self, addr: VirtAddr, owner: Owner) -> bool {
+        match self.allocation_mut(addr.as_u64()) {
+            Some(allocation) => {
+                allocation.owner = owner;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the allocation that contains `addr`, including its guard pages.
+    pub fn allocation(&self, addr: VirtAddr) -> Option<&Allocation> {
+        self.allocations
+            .range(..=addr.as_u64())
+            .next_back()
+            .map(|(_, allocation)| allocation)
+            .filter(|allocation| allocation.reserved.contains(&addr.as_u64()))
+    }
+
+    pub fn allocations(&self) -> impl Iterator<Item = &Allocation> {
+        self.allocations.values()
+    }
+
+    fn allocation_mut(&mut self, addr: u64) -> Option<&mut Allocation> {
+        self.allocations
+            .range_mut(..=addr)
+            .next_back()
+            .map(|(_, allocation)| allocation)
+            .filter(|allocation| allocation.reserved.contains(&addr))
+    }
+
+    /// Changes the attributes of pages that are mapped already, like the ones
+    /// set up by the bootloader. Huge pages are changed as a whole.
+    pub fn set_attributes(
+        &mut self,
+        range: Range<VirtAddr>,
+        attributes: Attributes,
+    ) -> Result<(), FlagUpdateError> {
+        let flags = attributes.flags();
+        let mut addr = range.start.align_down(Size4KiB::SIZE);
+
+        while addr < range.end {
+            let size = match self.mapper.translate(addr) {
+                TranslateResult::Mapped { frame, .. } => frame.size(),
+                _ => return Err(FlagUpdateError::PageNotMapped),
+            };
+
+            unsafe {
+                match size {
+                    Size4KiB::SIZE => self
+                        .mapper
+                        .update_flags(Page::<Size4KiB>::containing_address(addr), flags)?
+                        .flush(),
+                    Size2MiB::SIZE => self
+                        .mapper
+                        .update_flags(
+                            Page::<Size2MiB>::containing_address(addr),
+                            flags | Flags::HUGE_PAGE,
+                        )?
+                        .flush(),
+                    _ => self
+                        .mapper
+                        .update_flags(
+                            Page::<Size1GiB>::containing_address(addr),
+                            flags | Flags::HUGE_PAGE,
+                        )?
+                        .flush(),
+                }
+            }
+
+            addr = addr.align_down(size) + size;
+        }
+
+        Ok(())
+    }
+
+    fn reserve_page_range<S: PageSize>(
+        &mut self,
+        page_count: u64,
+        guard: u64,
+    ) -> Option<(Range<u64>, PageRange<S>)> {
+        let needed_size = x86_64::align_up(page_count * S::SIZE, S::SIZE) + 2 * guard;
+
+        self.free_regions.reserve(needed_size, S::SIZE).map(|r| {
+            let addr = VirtAddr::new(r.start + guard);
             let first = Page::<S>::from_start_address(addr).unwrap();
             let last = first + page_count;
-            Page::range(first, last)
+            (r, Page::range(first, last))
         })
     }
 
-    fn map_page_range<S>(&mut self, pages: PageRange<S>, phys_start: PhysAddr)
-    where
+    fn map_page_range<S>(
+        &mut self,
+        pages: PageRange<S>,
+        phys_start: PhysAddr,
+        attributes: Attributes,
+    ) where
         S: PageSize + fmt::Debug,
         OffsetPageTable<'a>: Mapper<S>,
     {
@@ -91,19 +332,32 @@ where
         let frames = PhysFrame::range_inclusive(first, last);
 
         for (page, frame) in pages.zip(frames) {
-            unsafe {
-                self.mapper.map_to(
-                    page,
-                    frame,
-                    Flags::PRESENT | Flags::WRITABLE,
-                    self.frame_allocator.lock().deref_mut(),
-                )
+            let tables = {
+                let mut frame_allocator = self.frame_allocator.lock();
+                let mut table_frames = TableFrames::new(frame_allocator.deref_mut());
+                unsafe {
+                    // parent tables stay writable so that they can hold both
+                    // read-only and writable mappings
+                    self.mapper.map_to_with_table_flags(
+                        page,
+                        frame,
+                        attributes.flags(),
+                        Flags::PRESENT | Flags::WRITABLE,
+                        &mut table_frames,
+                    )
+                }
+                .unwrap()
+                .flush();
+                table_frames.allocated
+            };
+            if let Some(table) = tables[0] {
+                self.note_new_table(page.start_address(), table);
             }
-            .unwrap()
-            .flush();
+            self.tables.extend(tables.into_iter().flatten());
         }
     }
 
+    /// Unmaps a whole allocation, or the pages at its end.
     pub fn deallocate<S: PageSize>(&mut self, pages: PageRange<S>) -> bool
     where
         S: PageSize + fmt::Debug,
@@ -117,290 +371,152 @@ where
         S: PageSize + fmt::Debug,
         OffsetPageTable<'a>: Mapper<S>,
     {
-        let addr = pages.start.start_address().as_u64();
-        let size = (pages.end - pages.start) * S::SIZE;
-        if self.release_range(addr..(addr + size)) {
-            self.unmap_page_range(pages);
-            true
+        let start = pages.start.start_address().as_u64();
+        let end = start + (pages.end - pages.start) * S::SIZE;
+
+        let Some(allocation) = self.allocation_mut(start) else {
+            return false;
+        };
+        if start < allocation.mapped.start || end != allocation.mapped.end {
+            return false;
+        }
+
+        let whole = start == allocation.mapped.start;
+        let released = if whole {
+            allocation.reserved.clone()
         } else {
-            false
+            // the trailing guard pages move down to the new end
+            let guard = allocation.reserved.end - allocation.mapped.end;
+            (start + guard)..allocation.reserved.end
+        };
+        let key = allocation.reserved.start;
+        let owns_frames = allocation.owns_frames;
+
+        if !self.free_regions.release(released.clone()) {
+            return false;
         }
+
+        if whole {
+            self.allocations.remove(&key);
+        } else if let Some(allocation) = self.allocations.get_mut(&key) {
+            allocation.mapped.end = start;
+            allocation.reserved.end = released.start;
+        }
+
+        self.unmap_page_range(pages, owns_frames);
+        true
     }
 
-    fn unmap_page_range<S>(&mut self, pages: PageRange<S>)
+    fn unmap_page_range<S>(&mut self, pages: PageRange<S>, owns_frames: bool)
     where
         S: PageSize + fmt::Debug,
         OffsetPageTable<'a>: Mapper<S>,
     {
-        for page in pages.into_iter() {
-            self.mapper.unmap(page).unwrap().1.flush();
+        if pages.is_empty() {
+            return;
         }
-    }
-
-    pub fn usable_regions(&self) -> Vec<Range<u64>> {
-        let mut res: Vec<_> = self
-            .free_regions
-            .iter()
-            .map(|(size, addr)| (*size, *addr))
-            .collect();
-        res.sort_unstable_by(|a, b| a.1.cmp(&b.1));
-        res.into_iter()
-            .map(|(size, addr)| addr..(addr + size))
-            .collect()
-    }
-}
-
-pub(crate) trait ReserveRegion {
-    fn free_regions(&mut self) -> &mut BTreeMap<u64, u64>;
-
-    fn reserve_range(&mut self, needed_size: u64, alignment: u64) -> Option<Range<u64>> {
-        let free_regions = self.free_regions();
-        assert!(needed_size > 0, "size must be non-zero");
 
-        for (&size, &addr) in free_regions.iter() {
-            let aligned = x86_64::align_up(addr, alignment);
-            let padding = aligned - addr;
-
-            if needed_size + padding > size {
-                continue;
-            }
-
-            free_regions.remove(&size);
-
-            let remaining = size - needed_size - padding;
-            if remaining > 0 {
-                *free_regions.entry(remaining).or_default() = aligned + needed_size;
-            }
+        for page in pages.into_iter() {
+            let (frame, flush) = self.mapper.unmap(page).unwrap();
+            flush.flush();
 
-            if aligned != addr {
-                *free_regions.entry(padding).or_default() = addr;
+            // only 4 KiB frames come from the frame allocator
+            if owns_frames && S::SIZE == Size4KiB::SIZE {
+                let frame = PhysFrame::from_start_address(frame.start_address()).unwrap();
+                unsafe { self.frame_allocator.lock().deallocate_frame(frame) };
             }
+        }
 
-            return Some(aligned..(aligned + needed_size));
+        let end = pages.end.start_address();
+        let mut addr = pages.start.start_address().align_down(Size2MiB::SIZE);
+        while addr < end {
+            self.free_empty_tables(addr);
+            addr += Size2MiB::SIZE;
         }
-        None
     }
 
-    fn release_range(&mut self, region: Range<u64>) -> bool {
-        let free_regions = self.free_regions();
-        let region_addr = region.start;
-        let region_size = region.end - region.start;
-        assert!(region_size > 0, "size must be non-zero");
-
-        let mut regions: Vec<(u64, u64)> = free_regions
-            .iter()
-            .map(|(&size, &addr)| (addr, size))
-            .collect();
-
-        regions.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-
-        match regions.binary_search_by(|&(addr, size)| {
-            if (addr..(addr + size)).contains(&region_addr) {
-                Ordering::Equal
+    /// Frees the level 1 and 2 tables on the way to `addr` if they became
+    /// empty and were allocated by the manager. Level 3 tables are kept, so
+    /// that entries of the level 4 table never change.
+    fn free_empty_tables(&mut self, addr: VirtAddr) {
+        let phys_offset = self.mapper.phys_offset();
+        let table = |entry: &PageTableEntry| -> Option<&mut PageTable> {
+            if entry.is_unused() || entry.flags().contains(Flags::HUGE_PAGE) {
+                None
             } else {
-                addr.cmp(&region_addr)
+                Some(unsafe { &mut *(phys_offset + entry.addr().as_u64()).as_mut_ptr() })
+            }
+        };
+
+        let Some(p3) = table(&self.mapper.level_4_table()[addr.p4_index()]) else {
+            return;
+        };
+        let Some(p2) = table(&p3[addr.p3_index()]) else {
+            return;
+        };
+
+        let mut frame_allocator = self.frame_allocator.lock();
+        let p2_entry = &mut p2[addr.p2_index()];
+        if let Some(p1) = table(p2_entry) {
+            let frame = PhysFrame::containing_address(p2_entry.addr());
+            if p1.iter().all(PageTableEntry::is_unused) && self.tables.remove(&frame) {
+                p2_entry.set_unused();
+                unsafe { frame_allocator.deallocate_frame(frame) };
             }
-        }) {
-            Ok(_) => return false,
-            Err(i) => regions.insert(i, (region_addr, region_size)),
         }
 
-        free_regions.clear();
-        regions
-            .into_iter()
-            .map(|(addr, size)| (addr, addr + size))
-            .fold(vec![], |mut acc: Vec<(u64, u64)>, (start, end)| {
-                match acc.last_mut() {
-                    Some(last) if last.1 == start => last.1 = end,
-                    _ => acc.push((start, end)),
-                }
-                acc
-            })
-            .into_iter()
-            .for_each(|(start, end)| *free_regions.entry(end - start).or_default() = start);
+        let p3_entry = &mut p3[addr.p3_index()];
+        let frame = PhysFrame::containing_address(p3_entry.addr());
+        if p2.iter().all(PageTableEntry::is_unused) && self.tables.remove(&frame) {
+            p3_entry.set_unused();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
 
-        true
+        tlb::flush(addr);
     }
-}
 
-pub fn get_mappings(mapper: &mut OffsetPageTable) -> Vec<VirtMapping> {
-    let mut res = Vec::new();
-
-    let offset = mapper.phys_offset().as_u64();
-    for (i, e) in mapper
-        .level_4_table()
-        .iter()
-        .enumerate()
-        .filter(|(_, e)| !e.is_unused())
-    {
-        let virt = (i as u64) << 12 << 9 << 9 << 9;
-        let phys = e.addr().as_u64();
-        let page_dir_ptr_table = unsafe { &*((phys + offset) as *const u64 as *const PageTable) };
-
-        for (i, e) in page_dir_ptr_table
-            .iter()
-            .enumerate()
-            .filter(|(_, e)| !e.is_unused())
-        {
-            let virt = virt | (i as u64) << 12 << 9 << 9;
-            let phys = e.addr().as_u64();
-            if e.flags().contains(PageTableFlags::HUGE_PAGE) {
-                let virt = VirtAddr::new(virt);
-                let phys = PhysAddr::new(phys);
-                res.push(VirtMapping {
-                    virt: Pages::Huge(Page::from_start_address(virt).unwrap()),
-                    phys: PhysFrames::Huge(PhysFrame::from_start_address(phys).unwrap()),
-                });
-                continue;
-            }
-
-            let page_dir_table = unsafe { &*((phys + offset) as *const u64 as *const PageTable) };
-            for (i, e) in page_dir_table
-                .iter()
-                .enumerate()
-                .filter(|(_, e)| !e.is_unused())
-            {
-                let virt = virt | (i as u64) << 12 << 9;
-                let phys = e.addr().as_u64();
-                if e.flags().contains(PageTableFlags::HUGE_PAGE) {
-                    let virt = VirtAddr::new(virt);
-                    let phys = PhysAddr::new(phys);
-                    res.push(VirtMapping {
-                        virt: Pages::Large(Page::from_start_address(virt).unwrap()),
-                        phys: PhysFrames::Large(PhysFrame::from_start_address(phys).unwrap()),
-                    });
-                    continue;
-                }
-
-                let page_table = unsafe { &*((phys + offset) as *const u64 as *const PageTable) };
-                for (i, _) in page_table
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, e)| !e.is_unused())
-                {
-                    let virt = virt | (i as u64) << 12;
-                    let phys = e.addr().as_u64();
-
-                    let virt = VirtAddr::new(virt);
-                    let phys = PhysAddr::new(phys);
-                    res.push(VirtMapping {
-                        virt: Pages::Regular(Page::from_start_address(virt).unwrap()),
-                        phys: PhysFrames::Regular(PhysFrame::from_start_address(phys).unwrap()),
-                    });
-                }
-            }
-        }
+    /// Reads the page tables of the manager.
+    pub fn walker(&mut self) -> PageTableWalker<'_> {
+        self.sync_kernel_half();
+        PageTableWalker::new(&mut self.mapper)
     }
 
-    res
-}
-
-impl<T: ?Sized> MappedRegions for T where T: IntoIterator<Item = VirtMapping> {}
-pub trait MappedRegions: IntoIterator<Item = VirtMapping> {
-    fn into_regions(self) -> Vec<MappedRegion>
-    where
-        Self: Sized,
-    {
-        self.into_iter()
-            .map(|m| {
-                (
-                    m.virt.start_address().as_u64(),
-                    m.virt.start_address().as_u64() + m.virt.size(),
-                    m.phys.start_address().as_u64(),
-                    m.phys.start_address().as_u64() + m.phys.size(),
-                )
-            })
-            .map(|(virt_start, virt_end, phys_start, phys_end)| {
-                (virt_start..virt_end, phys_start..phys_end)
-            })
-            .fold(vec![], |mut acc: Vec<_>, (virt, phys)| {
-                match acc.last_mut() {
-                    Some(last) if last.virt.end == virt.start => last.virt.end = virt.end,
-                    _ => acc.push(MappedRegion { virt, phys }),
-                }
-                acc
-            })
+    pub fn translate(&mut self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.walker().translate(addr)
     }
-}
 
-const FIRST_ADDRESS: u64 = 10 * Size4KiB::SIZE;
-const LAST_ADDRESS: u64 = 1_u64 << 48;
-
-impl<T: ?Sized> UsableRegions for T where T: IntoIterator<Item = MappedRegion> {}
-pub trait UsableRegions: IntoIterator<Item = MappedRegion> {
-    fn into_usable(self) -> Vec<Range<u64>>
-    where
-        Self: Sized,
-    {
-        let mut res = Vec::new();
-        let mut current = FIRST_ADDRESS;
-
-        for region in self.into_iter() {
-            res.push(current..region.virt.start);
-            current = region.virt.end;
-        }
-
-        if current < LAST_ADDRESS {
-            res.push(current..LAST_ADDRESS);
-        }
-        res.into_iter().filter(|r| r.start < r.end).collect()
+    pub fn flags(&mut self, addr: VirtAddr) -> Option<Flags> {
+        self.walker().flags(addr)
     }
-}
-
-pub struct VirtMapping {
-    pub virt: Pages,
-    pub phys: PhysFrames,
-}
 
-pub struct MappedRegion {
-    pub virt: Range<u64>,
-    pub phys: Range<u64>,
-}
-
-pub enum Pages {
-    Regular(Page<Size4KiB>),
-    Large(Page<Size2MiB>),
-    Huge(Page<Size1GiB>),
+    pub fn usable_regions(&self) -> Vec<Range<u64>> {
+        self.free_regions.regions().collect()
+    }
 }
 
-pub enum PhysFrames {
-    Regular(PhysFrame<Size4KiB>),
-    Large(PhysFrame<Size2MiB>),
-    Huge(PhysFrame<Size1GiB>),
+/// Hands out frames for page tables and keeps them until they can be
+/// recorded. Recording them while the frame allocator is locked could grow
+/// the heap, which needs the frame allocator itself.
+struct TableFrames<'b, A> {
+    inner: &'b mut A,
+    /// A single mapping needs at most a level 3, 2 and 1 table.
+    allocated: [Option<PhysFrame>; 3],
 }
 
-impl Pages {
-    fn start_address(&self) -> VirtAddr {
-        match self {
-            Self::Regular(p) => p.start_address(),
-            Self::Large(p) => p.start_address(),
-            Self::Huge(p) => p.start_address(),
-        }
-    }
-
-    fn size(&self) -> u64 {
-        match self {
-            Self::Regular(p) => p.size(),
-            Self::Large(p) => p.size(),
-            Self::Huge(p) => p.size(),
+impl<'b, A> TableFrames<'b, A> {
+    fn new(inner: &'b mut A) -> Self {
+        TableFrames {
+            inner,
+            allocated: [None; 3],
         }
     }
 }
 
-impl PhysFrames {
-    fn start_address(&self) -> PhysAddr {
-        match self {
-            Self::Regular(p) => p.start_address(),
-            Self::Large(p) => p.start_address(),
-            Self::Huge(p) => p.start_address(),
-        }
-    }
-
-    fn size(&self) -> u64 {
-        match self {
-            Self::Regular(p) => p.size(),
-            Self::Large(p) => p.size(),
-            Self::Huge(p) => p.size(),
-        }
+unsafe impl<A: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB> for TableFrames<'_, A> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let slot = self.allocated.iter_mut().find(|slot| slot.is_none())?;
+        let frame = self.inner.allocate_frame()?;
+        *slot = Some(frame);
+        Some(frame)
     }
 }
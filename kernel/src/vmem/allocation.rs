@@ -0,0 +1,61 @@
+use alloc::string::String;
+use core::fmt;
+use core::ops::Range;
+
+/// What a range of virtual memory is used for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Owner {
+    Heap,
+    Framebuffer,
+    Acpi,
+    /// Memory-mapped device registers.
+    Mmio,
+    /// The table listing the pools of an NVDIMM.
+    PoolTable,
+    /// A pool with the given name.
+    Pool(String),
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Heap => f.write_str("heap"),
+            Self::Framebuffer => f.write_str("framebuffer"),
+            Self::Acpi => f.write_str("ACPI"),
+            Self::Mmio => f.write_str("MMIO"),
+            Self::PoolTable => f.write_str("pool table"),
+            Self::Pool(name) => write!(f, "pool '{}'", name),
+        }
+    }
+}
+
+/// A virtual allocation and the unmapped guard pages around it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    /// The addresses taken from the free regions, including guard pages.
+    pub reserved: Range<u64>,
+    /// The addresses that are mapped.
+    pub mapped: Range<u64>,
+    pub owner: Owner,
+    /// Whether the frames came from the frame allocator and are given back
+    /// when they are unmapped.
+    pub owns_frames: bool,
+}
+
+impl Allocation {
+    pub fn is_guard(&self, addr: u64) -> bool {
+        self.reserved.contains(&addr) && !self.mapped.contains(&addr)
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:012x}-0x{:012x}",
+            self.owner,
+            self.mapped.start,
+            self.mapped.end - 1,
+        )
+    }
+}
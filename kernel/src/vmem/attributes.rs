@@ -0,0 +1,139 @@
+use core::arch::asm;
+use x86_64::instructions::tlb;
+use x86_64::registers::model_specific::{Efer, EferFlags, Msr};
+use x86_64::structures::paging::PageTableFlags as Flags;
+
+const IA32_PAT: u32 = 0x277;
+
+/// Memory types as encoded in the PAT.
+const PAT_UC: u64 = 0x00;
+const PAT_WC: u64 = 0x01;
+const PAT_WT: u64 = 0x04;
+const PAT_WB: u64 = 0x06;
+
+/// PAT entries 0-3 selected by PWT and PCD, repeated for 4-7 so the PAT bit,
+/// which sits at a different position in huge pages, is never needed.
+const PAT: u64 = {
+    let low = PAT_WB | PAT_WC << 8 | PAT_WT << 16 | PAT_UC << 24;
+    low | low << 32
+};
+
+/// How accesses to a mapping are cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    WriteBack,
+    /// Writes are combined into bursts but not cached, for framebuffers.
+    WriteCombining,
+    WriteThrough,
+    /// Every access goes to the device, for memory-mapped registers.
+    Uncacheable,
+}
+
+impl CacheType {
+    fn flags(self) -> Flags {
+        match self {
+            Self::WriteBack => Flags::empty(),
+            Self::WriteCombining => Flags::WRITE_THROUGH,
+            Self::WriteThrough => Flags::NO_CACHE,
+            Self::Uncacheable => Flags::WRITE_THROUGH | Flags::NO_CACHE,
+        }
+    }
+}
+
+/// Attributes of a mapping. Defaults to writable, non-executable, non-global
+/// write-back memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    cache: CacheType,
+    writable: bool,
+    executable: bool,
+    global: bool,
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Attributes {
+    pub const fn new() -> Self {
+        Attributes {
+            cache: CacheType::WriteBack,
+            writable: true,
+            executable: false,
+            global: false,
+        }
+    }
+
+    pub const fn cache(mut self, cache: CacheType) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub const fn writable(mut self, writable: bool) -> Self {
+        self.writable = writable;
+        self
+    }
+
+    pub const fn executable(mut self, executable: bool) -> Self {
+        self.executable = executable;
+        self
+    }
+
+    /// Global mappings stay in the TLB when CR3 is reloaded.
+    pub const fn global(mut self, global: bool) -> Self {
+        self.global = global;
+        self
+    }
+
+    pub fn flags(self) -> Flags {
+        let mut flags = Flags::PRESENT | self.cache.flags();
+        flags.set(Flags::WRITABLE, self.writable);
+        flags.set(Flags::NO_EXECUTE, !self.executable);
+        flags.set(Flags::GLOBAL, self.global);
+        flags
+    }
+}
+
+/// Programs the PAT for the cache types above and allows non-executable
+/// mappings.
+///
+/// # Safety
+///
+/// Changes how existing mappings using PWT or PCD are cached, so it must be
+/// called before any such mapping is created.
+pub unsafe fn init() {
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+
+    Msr::new(IA32_PAT).write(PAT);
+    asm!("wbinvd", options(nostack, preserves_flags));
+    tlb::flush_all();
+}
+
+#[test_case]
+fn cache_types_select_programmed_pat_entries() {
+    use CacheType::*;
+
+    // programmed by `crate::init`
+    let pat = unsafe { Msr::new(IA32_PAT).read() };
+    let expected = [
+        (WriteBack, Flags::empty(), PAT_WB),
+        (WriteCombining, Flags::WRITE_THROUGH, PAT_WC),
+        (WriteThrough, Flags::NO_CACHE, PAT_WT),
+        (Uncacheable, Flags::WRITE_THROUGH | Flags::NO_CACHE, PAT_UC),
+    ];
+    for (cache, bits, memory_type) in expected {
+        let flags = Attributes::new().cache(cache).flags();
+        let cache_bits = Flags::WRITE_THROUGH | Flags::NO_CACHE;
+        assert_eq!(flags & cache_bits, bits, "{:?}", cache);
+        // the PAT bit of 4 KiB pages, which is the huge page bit in tables
+        assert!(!flags.contains(Flags::HUGE_PAGE), "{:?}", cache);
+
+        let index = u64::from(flags.contains(Flags::WRITE_THROUGH))
+            | u64::from(flags.contains(Flags::NO_CACHE)) << 1;
+        assert_eq!(pat >> (index * 8) & 0xff, memory_type, "{:?}", cache);
+        // the same entry with the PAT bit set
+        assert_eq!(pat >> ((index + 4) * 8) & 0xff, memory_type, "{:?}", cache);
+    }
+}
@@ -0,0 +1,157 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::ops::Range;
+
+/// Strategy for picking one of several free regions that fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// The region with the lowest address. Keeps the start of the space
+    /// densely used, but walks the free regions in address order.
+    First,
+    /// The smallest region, found through the size index.
+    Best,
+}
+
+/// Hands out ranges of an address space and takes them back.
+///
+/// Free regions are kept by address, so releasing a range merges it with its
+/// neighbours in O(log n), and by size for best-fit lookups.
+#[derive(Debug, Clone)]
+pub struct RegionAllocator {
+    /// Maps the start of each free region to its end. Regions never overlap
+    /// or touch, as touching regions are merged.
+    by_addr: BTreeMap<u64, u64>,
+    /// The same regions as `(size, start)`.
+    by_size: BTreeSet<(u64, u64)>,
+    fit: Fit,
+}
+
+impl RegionAllocator {
+    pub const fn new(fit: Fit) -> Self {
+        RegionAllocator {
+            by_addr: BTreeMap::new(),
+            by_size: BTreeSet::new(),
+            fit,
+        }
+    }
+
+    /// Creates an allocator with the given ranges free. Empty ranges and
+    /// ranges overlapping a previous one are skipped.
+    pub fn with_regions(fit: Fit, regions: impl IntoIterator<Item = Range<u64>>) -> Self {
+        let mut allocator = Self::new(fit);
+        for region in regions.into_iter().filter(|r| !r.is_empty()) {
+            allocator.release(region);
+        }
+        allocator
+    }
+
+    pub fn fit(&self) -> Fit {
+        self.fit
+    }
+
+    pub fn set_fit(&mut self, fit: Fit) {
+        self.fit = fit;
+    }
+
+    /// Reserves `size` bytes starting at a multiple of `alignment`, which
+    /// must be a power of two.
+    pub fn reserve(&mut self, size: u64, alignment: u64) -> Option<Range<u64>> {
+        assert!(size > 0, "size must be non-zero");
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
+        let (start, end, aligned) = match self.fit {
+            Fit::First => self.by_addr.iter().find_map(|(&start, &end)| {
+                fits(start, end, size, alignment).map(|aligned| (start, end, aligned))
+            }),
+            Fit::Best => self.by_size.range((size, 0)..).find_map(|&(len, start)| {
+                fits(start, start + len, size, alignment)
+                    .map(|aligned| (start, start + len, aligned))
+            }),
+        }?;
+
+        self.remove(start);
+        if start < aligned {
+            self.insert(start, aligned);
+        }
+        if aligned + size < end {
+            self.insert(aligned + size, end);
+        }
+
+        Some(aligned..(aligned + size))
+    }
+
+    /// Reserves the given range. Returns `false` without changing anything
+    /// if part of it isn't free.
+    pub fn take(&mut self, region: Range<u64>) -> bool {
+        assert!(!region.is_empty(), "size must be non-zero");
+        let Some((&start, &end)) = self.by_addr.range(..=region.start).next_back() else {
+            return false;
+        };
+        if end < region.end {
+            return false;
+        }
+
+        self.remove(start);
+        if start < region.start {
+            self.insert(start, region.start);
+        }
+        if region.end < end {
+            self.insert(region.end, end);
+        }
+        true
+    }
+
+    /// Gives a range back. Returns `false` without changing anything if
+    /// part of the range is free already.
+    pub fn release(&mut self, region: Range<u64>) -> bool {
+        assert!(!region.is_empty(), "size must be non-zero");
+        let Range { mut start, mut end } = region;
+
+        // the last region starting before the end is the only one that can
+        // overlap or precede the range directly
+        if let Some((&prev_start, &prev_end)) = self.by_addr.range(..end).next_back() {
+            if prev_end > start {
+                return false;
+            }
+            if prev_end == start {
+                self.remove(prev_start);
+                start = prev_start;
+            }
+        }
+        if let Some(next_end) = self.remove(end) {
+            end = next_end;
+        }
+
+        self.insert(start, end);
+        true
+    }
+
+    /// Returns the free regions in address order.
+    pub fn regions(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        self.by_addr.iter().map(|(&start, &end)| start..end)
+    }
+
+    /// Returns the number of free bytes.
+    pub fn free_space(&self) -> u64 {
+        self.by_size.iter().map(|&(size, _)| size).sum()
+    }
+
+    fn insert(&mut self, start: u64, end: u64) {
+        self.by_addr.insert(start, end);
+        self.by_size.insert((end - start, start));
+    }
+
+    fn remove(&mut self, start: u64) -> Option<u64> {
+        let end = self.by_addr.remove(&start)?;
+        self.by_size.remove(&(end - start, start));
+        Some(end)
+    }
+}
+
+/// Returns the aligned start of `size` bytes within `start..end`, if they fit.
+fn fits(start: u64, end: u64, size: u64, alignment: u64) -> Option<u64> {
+    let aligned = start.checked_add(alignment - 1)? & !(alignment - 1);
+    (aligned.checked_add(size)? <= end).then_some(aligned)
+}
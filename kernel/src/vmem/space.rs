@@ -0,0 +1,233 @@
+use super::Manager;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut, Range};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTable, PageTableFlags as Flags,
+    PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Level 4 entries of the lower half, which always belong to the kernel.
+const KERNEL_HALF: Range<usize> = 0..256;
+
+/// Counts the level 4 entries set in the kernel half, so that address
+/// spaces know when to copy them.
+static KERNEL_HALF_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The tables an address space copies the kernel half from.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct KernelHalf {
+    level_4_frame: PhysFrame,
+    /// The generation of the kernel half when it was last copied.
+    generation: u64,
+}
+
+/// Page tables of their own that share the kernel's mappings.
+///
+/// The lower half and every level 4 entry the kernel uses in the upper half
+/// point to the kernel's tables, so kernel mappings appear in all address
+/// spaces. Entries the kernel sets in the lower half later are copied before
+/// the tables are walked or activated. Mappings made through an address
+/// space go to the remaining level 4 entries of the upper half and appear
+/// in no other.
+#[derive(Debug)]
+pub struct AddressSpace<'a, A>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    manager: Manager<'a, A>,
+    level_4_frame: PhysFrame,
+    /// Level 4 entries whose tables belong to this address space.
+    private_entries: Vec<usize>,
+}
+
+impl<'a, A> Manager<'a, A>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    /// Creates an address space sharing the mappings of this one.
+    pub fn create_address_space(&mut self) -> Option<AddressSpace<'a, A>> {
+        self.sync_kernel_half();
+        let kernel_half = KernelHalf {
+            level_4_frame: match self.kernel_half {
+                Some(kernel_half) => kernel_half.level_4_frame,
+                None => PhysFrame::containing_address(PhysAddr::new(self.physical_address())),
+            },
+            generation: KERNEL_HALF_GENERATION.load(Ordering::Acquire),
+        };
+
+        let level_4_frame = self.frame_allocator.lock().allocate_frame()?;
+        let level_4_table = unsafe { self.table(level_4_frame) };
+        level_4_table.zero();
+
+        let mut private_entries = Vec::new();
+        let mut usable = Vec::new();
+        for (i, entry) in self.mapper.level_4_table().iter().enumerate() {
+            if !entry.is_unused() {
+                level_4_table[i] = entry.clone();
+            } else if i < 511 && !KERNEL_HALF.contains(&i) {
+                // the last entry is left out so that the range doesn't wrap
+                let start = VirtAddr::new_truncate((i as u64) << 39).as_u64();
+                private_entries.push(i);
+                usable.push(start..(start + (1 << 39)));
+            }
+        }
+
+        let mapper = unsafe { OffsetPageTable::new(level_4_table, self.mapper.phys_offset()) };
+        let mut manager = Manager::new(mapper, self.frame_allocator, usable);
+        manager.kernel_half = Some(kernel_half);
+        Some(AddressSpace {
+            manager,
+            level_4_frame,
+            private_entries,
+        })
+    }
+
+    /// Lets address spaces know if mapping `addr` set a level 4 entry of the
+    /// kernel half, given the first table that was allocated for it. Level 3
+    /// tables are never freed, so the entry doesn't change afterwards.
+    pub(super) fn note_new_table(&mut self, addr: VirtAddr, table: PhysFrame) {
+        let i = usize::from(addr.p4_index());
+        if KERNEL_HALF.contains(&i)
+            && self.mapper.level_4_table()[i].addr() == table.start_address()
+        {
+            KERNEL_HALF_GENERATION.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    /// Copies the level 4 entries the kernel set in the lower half since
+    /// this address space last did. Does nothing for the kernel's tables.
+    pub(super) fn sync_kernel_half(&mut self) {
+        let Some(kernel_half) = self.kernel_half.as_mut() else {
+            return;
+        };
+        let generation = KERNEL_HALF_GENERATION.load(Ordering::Acquire);
+        if kernel_half.generation == generation {
+            return;
+        }
+        kernel_half.generation = generation;
+
+        let addr = self.mapper.phys_offset() + kernel_half.level_4_frame.start_address().as_u64();
+        let kernel_table: &PageTable = unsafe { &*addr.as_ptr() };
+        let level_4_table = self.mapper.level_4_table();
+        for i in KERNEL_HALF {
+            if level_4_table[i].is_unused() {
+                level_4_table[i] = kernel_table[i].clone();
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The frame must hold a page table that isn't referenced elsewhere.
+    unsafe fn table(&self, frame: PhysFrame) -> &'a mut PageTable {
+        &mut *(self.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    /// Returns whether the page tables of this manager are active.
+    pub fn is_active(&mut self) -> bool {
+        let (frame, _) = x86_64::registers::control::Cr3::read();
+        frame.start_address().as_u64() == self.physical_address()
+    }
+
+    /// Switches to the page tables of this manager.
+    ///
+    /// # Safety
+    ///
+    /// The page tables must stay alive while they are active, and the code
+    /// and stack in use must be mapped in them.
+    pub unsafe fn activate(&mut self) {
+        use x86_64::registers::control::Cr3;
+
+        self.sync_kernel_half();
+        let frame = PhysFrame::containing_address(PhysAddr::new(self.physical_address()));
+        let (_, flags) = Cr3::read();
+        Cr3::write(frame, flags);
+    }
+}
+
+impl<'a, A> AddressSpace<'a, A>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns whether `addr` can only be mapped through this address space.
+    pub fn is_private(&self, addr: VirtAddr) -> bool {
+        self.private_entries.contains(&usize::from(addr.p4_index()))
+    }
+
+    /// Frees the tables below a private level 4 entry, except those of huge
+    /// pages.
+    fn free_tables(&mut self, frame: PhysFrame, level: u8) {
+        if level > 1 {
+            let children: Vec<_> = unsafe { self.manager.table(frame) }
+                .iter()
+                .filter(|e| !e.is_unused() && !e.flags().contains(Flags::HUGE_PAGE))
+                .map(PageTableEntry::addr)
+                .collect();
+            for addr in children {
+                self.free_tables(PhysFrame::containing_address(addr), level - 1);
+            }
+        }
+        unsafe { self.manager.frame_allocator.lock().deallocate_frame(frame) };
+    }
+}
+
+impl<'a, A> Deref for AddressSpace<'a, A>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    type Target = Manager<'a, A>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.manager
+    }
+}
+
+impl<'a, A> DerefMut for AddressSpace<'a, A>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.manager
+    }
+}
+
+impl<'a, A> Drop for AddressSpace<'a, A>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+
+        let anonymous: Vec<_> = self
+            .manager
+            .allocations()
+            .filter(|a| a.owns_frames)
+            .map(|a| a.mapped.clone())
+            .collect();
+        for mapped in anonymous {
+            let start = Page::<Size4KiB>::containing_address(VirtAddr::new(mapped.start));
+            let end = Page::containing_address(VirtAddr::new(mapped.end));
+            self.manager.deallocate(Page::range(start, end));
+        }
+
+        for i in self.private_entries.clone() {
+            let entry = self.manager.mapper.level_4_table()[i].clone();
+            if !entry.is_unused() {
+                self.free_tables(PhysFrame::containing_address(entry.addr()), 3);
+            }
+        }
+        unsafe {
+            self.manager
+                .frame_allocator
+                .lock()
+                .deallocate_frame(self.level_4_frame)
+        };
+    }
+}
@@ -0,0 +1,306 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    OffsetPageTable, Page, PageSize, PageTable, PageTableFlags as Flags, PhysFrame, Size1GiB,
+    Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Size of the address space spanned by a level 4 table, with the upper half
+/// following the lower half directly.
+const LINEAR_END: u64 = 1 << 48;
+
+/// Flags that make up the attributes of a region. Flags like `ACCESSED`
+/// change on every access and would split regions needlessly.
+const REGION_FLAGS: Flags = Flags::PRESENT
+    .union(Flags::WRITABLE)
+    .union(Flags::USER_ACCESSIBLE)
+    .union(Flags::WRITE_THROUGH)
+    .union(Flags::NO_CACHE)
+    .union(Flags::GLOBAL)
+    .union(Flags::NO_EXECUTE);
+
+/// Reads page tables without changing them.
+#[derive(Clone, Copy)]
+pub struct PageTableWalker<'a> {
+    level_4_table: &'a PageTable,
+    phys_offset: VirtAddr,
+}
+
+enum Walk {
+    Mapped(VirtMapping),
+    /// Nothing is mapped in the naturally aligned block of the given size.
+    Unmapped(u64),
+}
+
+impl<'a> PageTableWalker<'a> {
+    pub fn new(mapper: &'a mut OffsetPageTable) -> Self {
+        let phys_offset = mapper.phys_offset();
+        PageTableWalker {
+            level_4_table: mapper.level_4_table(),
+            phys_offset,
+        }
+    }
+
+    /// Returns the physical address `addr` is mapped to.
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.mapping(addr)
+            .map(|m| m.phys.start_address() + (addr - m.virt.start_address()))
+    }
+
+    /// Returns the flags that apply to `addr`. A page is only writable or
+    /// user accessible if all tables on the way allow it, and isn't
+    /// executable if any of them forbids it.
+    pub fn flags(&self, addr: VirtAddr) -> Option<Flags> {
+        self.mapping(addr).map(|m| m.flags)
+    }
+
+    /// Returns the page containing `addr`.
+    pub fn mapping(&self, addr: VirtAddr) -> Option<VirtMapping> {
+        match self.walk(addr) {
+            Walk::Mapped(mapping) => Some(mapping),
+            Walk::Unmapped(_) => None,
+        }
+    }
+
+    /// Returns the pages overlapping `range` in address order.
+    pub fn mappings(&self, range: Range<VirtAddr>) -> Mappings<'a> {
+        Mappings {
+            walker: *self,
+            next: linear(range.start),
+            end: linear(range.end - 1u64) + 1,
+        }
+    }
+
+    /// Returns all mapped pages in address order.
+    pub fn iter(&self) -> Mappings<'a> {
+        Mappings {
+            walker: *self,
+            next: 0,
+            end: LINEAR_END,
+        }
+    }
+
+    fn walk(&self, addr: VirtAddr) -> Walk {
+        let p4_entry = &self.level_4_table[addr.p4_index()];
+        if p4_entry.is_unused() {
+            return Walk::Unmapped(Size1GiB::SIZE * 512);
+        }
+        let flags = p4_entry.flags();
+
+        let p3_entry = &self.table(p4_entry)[addr.p3_index()];
+        if p3_entry.is_unused() {
+            return Walk::Unmapped(Size1GiB::SIZE);
+        }
+        let flags = combine(flags, p3_entry.flags());
+        if p3_entry.flags().contains(Flags::HUGE_PAGE) {
+            return Walk::Mapped(VirtMapping {
+                virt: Pages::Huge(Page::containing_address(addr)),
+                phys: PhysFrames::Huge(PhysFrame::containing_address(p3_entry.addr())),
+                flags,
+            });
+        }
+
+        let p2_entry = &self.table(p3_entry)[addr.p2_index()];
+        if p2_entry.is_unused() {
+            return Walk::Unmapped(Size2MiB::SIZE);
+        }
+        let flags = combine(flags, p2_entry.flags());
+        if p2_entry.flags().contains(Flags::HUGE_PAGE) {
+            return Walk::Mapped(VirtMapping {
+                virt: Pages::Large(Page::containing_address(addr)),
+                phys: PhysFrames::Large(PhysFrame::containing_address(p2_entry.addr())),
+                flags,
+            });
+        }
+
+        let p1_entry = &self.table(p2_entry)[addr.p1_index()];
+        if p1_entry.is_unused() {
+            return Walk::Unmapped(Size4KiB::SIZE);
+        }
+        Walk::Mapped(VirtMapping {
+            virt: Pages::Regular(Page::containing_address(addr)),
+            phys: PhysFrames::Regular(PhysFrame::containing_address(p1_entry.addr())),
+            flags: combine(flags, p1_entry.flags()),
+        })
+    }
+
+    fn table(&self, entry: &PageTableEntry) -> &'a PageTable {
+        unsafe { &*(self.phys_offset + entry.addr().as_u64()).as_ptr() }
+    }
+}
+
+/// Applies the restrictions of a table entry to the entries below it.
+fn combine(parent: Flags, child: Flags) -> Flags {
+    let mut flags = child;
+    for flag in [Flags::WRITABLE, Flags::USER_ACCESSIBLE] {
+        flags.set(flag, parent.contains(flag) && child.contains(flag));
+    }
+    flags.set(
+        Flags::NO_EXECUTE,
+        parent.contains(Flags::NO_EXECUTE) || child.contains(Flags::NO_EXECUTE),
+    );
+    flags
+}
+
+/// Maps a canonical address to a gapless address space.
+fn linear(addr: VirtAddr) -> u64 {
+    addr.as_u64() & (LINEAR_END - 1)
+}
+
+pub struct Mappings<'a> {
+    walker: PageTableWalker<'a>,
+    next: u64,
+    end: u64,
+}
+
+impl<'a> Iterator for Mappings<'a> {
+    type Item = VirtMapping;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.end {
+            let (mapping, size) = match self.walker.walk(VirtAddr::new_truncate(self.next)) {
+                Walk::Mapped(mapping) => {
+                    let size = mapping.virt.size();
+                    (Some(mapping), size)
+                }
+                Walk::Unmapped(size) => (None, size),
+            };
+
+            self.next = (self.next & !(size - 1)) + size;
+            if mapping.is_some() {
+                return mapping;
+            }
+        }
+        None
+    }
+}
+
+pub fn get_mappings(mapper: &mut OffsetPageTable) -> Vec<VirtMapping> {
+    PageTableWalker::new(mapper).iter().collect()
+}
+
+impl<T: ?Sized> MappedRegions for T where T: IntoIterator<Item = VirtMapping> {}
+pub trait MappedRegions: IntoIterator<Item = VirtMapping> {
+    /// Merges pages that are contiguous both virtually and physically and
+    /// have the same attributes.
+    fn into_regions(self) -> Vec<MappedRegion>
+    where
+        Self: Sized,
+    {
+        self.into_iter()
+            .map(|m| MappedRegion {
+                virt: m.virt.start_address().as_u64()
+                    ..(m.virt.start_address().as_u64() + m.virt.size()),
+                phys: m.phys.start_address().as_u64()
+                    ..(m.phys.start_address().as_u64() + m.phys.size()),
+                flags: m.flags & REGION_FLAGS,
+            })
+            .fold(vec![], |mut acc: Vec<MappedRegion>, region| {
+                match acc.last_mut() {
+                    Some(last)
+                        if last.virt.end == region.virt.start
+                            && last.phys.end == region.phys.start
+                            && last.flags == region.flags =>
+                    {
+                        last.virt.end = region.virt.end;
+                        last.phys.end = region.phys.end;
+                    }
+                    _ => acc.push(region),
+                }
+                acc
+            })
+    }
+}
+
+const FIRST_ADDRESS: u64 = 10 * Size4KiB::SIZE;
+/// End of the lower half of the address space.
+const LAST_ADDRESS: u64 = 1_u64 << 47;
+
+impl<T: ?Sized> UsableRegions for T where T: IntoIterator<Item = MappedRegion> {}
+pub trait UsableRegions: IntoIterator<Item = MappedRegion> {
+    /// Returns the unmapped ranges of the lower half between the regions.
+    fn into_usable(self) -> Vec<Range<u64>>
+    where
+        Self: Sized,
+    {
+        let mut res = Vec::new();
+        let mut current = FIRST_ADDRESS;
+
+        for region in self.into_iter() {
+            res.push(current..region.virt.start.min(LAST_ADDRESS));
+            current = current.max(region.virt.end);
+        }
+
+        if current < LAST_ADDRESS {
+            res.push(current..LAST_ADDRESS);
+        }
+        res.into_iter().filter(|r| r.start < r.end).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtMapping {
+    pub virt: Pages,
+    pub phys: PhysFrames,
+    pub flags: Flags,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedRegion {
+    pub virt: Range<u64>,
+    pub phys: Range<u64>,
+    pub flags: Flags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pages {
+    Regular(Page<Size4KiB>),
+    Large(Page<Size2MiB>),
+    Huge(Page<Size1GiB>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysFrames {
+    Regular(PhysFrame<Size4KiB>),
+    Large(PhysFrame<Size2MiB>),
+    Huge(PhysFrame<Size1GiB>),
+}
+
+impl Pages {
+    pub fn start_address(&self) -> VirtAddr {
+        match self {
+            Self::Regular(p) => p.start_address(),
+            Self::Large(p) => p.start_address(),
+            Self::Huge(p) => p.start_address(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Regular(p) => p.size(),
+            Self::Large(p) => p.size(),
+            Self::Huge(p) => p.size(),
+        }
+    }
+}
+
+impl PhysFrames {
+    pub fn start_address(&self) -> PhysAddr {
+        match self {
+            Self::Regular(p) => p.start_address(),
+            Self::Large(p) => p.start_address(),
+            Self::Huge(p) => p.start_address(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Regular(p) => p.size(),
+            Self::Large(p) => p.size(),
+            Self::Huge(p) => p.size(),
+        }
+    }
+}
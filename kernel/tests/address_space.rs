@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::allocator::{HEAP_SIZE, HEAP_START};
+use kernel::memory::{self, BitmapFrameAllocator, FRAMES};
+use kernel::vmem::{self, Attributes, MappedRegions, Owner, UsableRegions};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    PHYS_OFFSET.store(phys_mem_offset.as_u64(), Ordering::Relaxed);
+    unsafe {
+        FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+/// Creates a manager of the active page tables, like the kernel does.
+fn manager() -> vmem::Manager<'static, BitmapFrameAllocator> {
+    let mut mapper = unsafe { memory::init(VirtAddr::new(PHYS_OFFSET.load(Ordering::Relaxed))) };
+    let usable = vmem::get_mappings(&mut mapper).into_regions().into_usable();
+
+    let mut manager = vmem::Manager::new(mapper, &FRAMES, usable);
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    manager.register(heap_start..(heap_start + HEAP_SIZE), Owner::Heap);
+    manager
+}
+
+#[test_case]
+fn upper_half_is_private() {
+    let mut manager = manager();
+    let mut space = manager.create_address_space().unwrap();
+    let mut other = manager.create_address_space().unwrap();
+
+    let pages = space
+        .allocate_anonymous(1, Attributes::new(), Owner::Heap)
+        .unwrap();
+    let addr = pages.start.start_address();
+    assert!(addr.as_u64() >= 1 << 47);
+    assert!(space.is_private(addr));
+    assert!(space.translate(addr).is_some());
+    assert_eq!(manager.translate(addr), None);
+    assert_eq!(other.translate(addr), None);
+
+    // the same address maps to a frame of its own in another space
+    let other_pages = other
+        .allocate_anonymous(1, Attributes::new(), Owner::Heap)
+        .unwrap();
+    assert_eq!(other_pages, pages);
+    assert_ne!(other.translate(addr), space.translate(addr));
+
+    assert!(space.deallocate(pages));
+    assert!(other.deallocate(other_pages));
+}
+
+#[test_case]
+fn lower_half_is_shared() {
+    let mut manager = manager();
+    let mut space = manager.create_address_space().unwrap();
+
+    // mapped after the space was created
+    let pages = manager
+        .allocate_anonymous(1, Attributes::new(), Owner::Heap)
+        .unwrap();
+    let addr = pages.start.start_address();
+    assert!(addr.as_u64() < 1 << 47);
+    assert!(!space.is_private(addr));
+    assert!(manager.translate(addr).is_some());
+    assert_eq!(space.translate(addr), manager.translate(addr));
+
+    assert!(manager.deallocate(pages));
+    assert_eq!(space.translate(addr), None);
+}
+
+#[test_case]
+fn dropping_frees_private_tables() {
+    let mut manager = manager();
+    // heap pages touched for the first time take frames that stay
+    drop(manager.create_address_space().unwrap());
+    let free = FRAMES.lock().free_frames();
+
+    let mut space = manager.create_address_space().unwrap();
+    // the kernel half is shared, not given tables up front
+    assert_eq!(FRAMES.lock().free_frames(), free - 1);
+    space
+        .allocate_anonymous(4, Attributes::new(), Owner::Heap)
+        .unwrap();
+    assert!(FRAMES.lock().free_frames() < free);
+    drop(space);
+
+    assert_eq!(FRAMES.lock().free_frames(), free);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
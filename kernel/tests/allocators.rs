@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, GlobalAlloc, Layout};
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::allocator::bump::BumpAllocator;
+use kernel::allocator::conformance::{self, HEAP_SIZE};
+use kernel::allocator::debug::{DebugAllocator, HeapError};
+use kernel::allocator::fixed_size_block::FixedSizeBlockAllocator;
+use kernel::allocator::linked_list::LinkedListAllocator;
+use kernel::allocator::{self, Locked};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::memory;
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+/// Takes memory for an allocator under test from the kernel heap. It is
+/// never given back.
+fn heap() -> usize {
+    let layout = Layout::from_size_align(HEAP_SIZE, 4096).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    assert!(!ptr.is_null());
+    ptr as usize
+}
+
+#[test_case]
+fn bump_allocator() {
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(heap(), HEAP_SIZE) };
+    conformance::run(&allocator);
+}
+
+#[test_case]
+fn linked_list_allocator() {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap(), HEAP_SIZE) };
+    conformance::run(&allocator);
+
+    // freed memory is merged again, including the padding of aligned blocks
+    assert_eq!(allocator.lock().stats().largest_free, Some(HEAP_SIZE));
+}
+
+#[test_case]
+fn linked_list_allocator_grows_in_place() {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap(), HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        assert_eq!(allocator.realloc(ptr, layout, 4096), ptr);
+        allocator.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap());
+    }
+}
+
+#[test_case]
+fn fixed_size_block_allocator() {
+    let allocator = FixedSizeBlockAllocator::new();
+    unsafe { allocator.init(heap(), HEAP_SIZE, HEAP_SIZE) };
+    conformance::run(&allocator);
+}
+
+#[test_case]
+fn fixed_size_block_allocator_releases_slabs() {
+    let allocator = FixedSizeBlockAllocator::new();
+    unsafe { allocator.init(heap(), HEAP_SIZE, HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    let blocks: Vec<_> = (0..10_000)
+        .map(|_| unsafe { allocator.alloc(layout) })
+        .collect();
+    assert!(allocator.stats().fallback.live() > 1);
+    for block in blocks {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+
+    // all but a spare slab are given back
+    assert_eq!(allocator.stats().fallback.live(), 1);
+}
+
+#[test_case]
+fn debug_allocator() {
+    let allocator = DebugAllocator::new(Locked::new(LinkedListAllocator::new()));
+    unsafe { allocator.inner().lock().init(heap(), HEAP_SIZE) };
+    conformance::run(&allocator);
+    assert_eq!(allocator.errors(), 0);
+}
+
+#[test_case]
+fn debug_allocator_detects_corruption() {
+    let allocator = DebugAllocator::new(Locked::new(LinkedListAllocator::new()));
+    unsafe { allocator.inner().lock().init(heap(), HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        ptr.add(24).write(0);
+        allocator.dealloc(ptr, layout);
+        assert_eq!(allocator.last_error(), Some(HeapError::Overflow));
+
+        allocator.dealloc(ptr, layout);
+        assert_eq!(allocator.last_error(), Some(HeapError::DoubleFree));
+
+        ptr.write(0);
+        allocator.dealloc(allocator.alloc(layout), layout);
+        assert_eq!(allocator.last_error(), Some(HeapError::UseAfterFree));
+    }
+    assert_eq!(allocator.errors(), 3);
+}
+
+/// The global allocator selected by the features of the kernel.
+struct Global;
+
+unsafe impl GlobalAlloc for Global {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dealloc(ptr, layout)
+    }
+}
+
+#[test_case]
+fn global_allocator() {
+    conformance::run(&Global);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
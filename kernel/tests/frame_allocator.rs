@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::FRAMES;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    unsafe {
+        FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset)
+    };
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn reuses_freed_frames() {
+    let mut frames = FRAMES.lock();
+    let free = frames.free_frames();
+
+    let a: PhysFrame<Size4KiB> = frames.allocate_frame().unwrap();
+    let b: PhysFrame<Size4KiB> = frames.allocate_frame().unwrap();
+    assert_ne!(a, b);
+    assert_ne!(a.start_address().as_u64(), 0);
+    assert_eq!(frames.free_frames(), free - 2);
+
+    unsafe { frames.deallocate_frame(a) };
+    let c: PhysFrame<Size4KiB> = frames.allocate_frame().unwrap();
+    assert_eq!(a, c);
+
+    unsafe { frames.deallocate_frame(b) };
+    unsafe { frames.deallocate_frame(c) };
+    assert_eq!(frames.free_frames(), free);
+}
+
+#[test_case]
+fn large_frames() {
+    let mut frames = FRAMES.lock();
+    let free = frames.free_frames();
+
+    let frame: PhysFrame<Size2MiB> = frames.allocate_frame().unwrap();
+    assert_eq!(frame.start_address().as_u64() % Size2MiB::SIZE, 0);
+    assert_eq!(frames.free_frames(), free - 512);
+
+    unsafe { frames.deallocate_frame(frame) };
+    assert_eq!(frames.free_frames(), free);
+}
+
+#[test_case]
+fn contiguous_aligned() {
+    let mut frames = FRAMES.lock();
+    let free = frames.free_frames();
+
+    let range = frames.allocate_contiguous(17, 0x10000).unwrap();
+    assert_eq!(range.end - range.start, 17);
+    assert_eq!(range.start.start_address().as_u64() % 0x10000, 0);
+
+    let next: PhysFrame<Size4KiB> = frames.allocate_frame().unwrap();
+    assert!(next < range.start || next >= range.end);
+
+    unsafe {
+        frames.deallocate_contiguous(range);
+        frames.deallocate_frame(next);
+    }
+    assert_eq!(frames.free_frames(), free);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -6,23 +6,25 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use bootloader_api::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use kernel::allocator::HEAP_SIZE;
+use kernel::allocator::{self, HEAP_SIZE};
 
 entry_point!(main);
 
 fn main(boot_info: &'static mut BootInfo) -> ! {
-    use kernel::allocator;
-    use kernel::memory::{self, BootInfoFrameAllocator};
+    use kernel::memory;
     use x86_64::VirtAddr;
 
     kernel::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
 
     test_main();
     loop {}
@@ -54,6 +56,8 @@ fn many_boxes() {
     }
 }
 
+// the bump allocator only reuses memory once everything is freed
+#[cfg(not(feature = "bump-allocator"))]
 #[test_case]
 fn many_boxes_long_lived() {
     let long_lived = Box::new(1); // new
@@ -64,6 +68,90 @@ fn many_boxes_long_lived() {
     assert_eq!(*long_lived, 1); // new
 }
 
+#[test_case]
+fn backed_on_demand() {
+    let before = allocator::committed();
+    assert!(before < HEAP_SIZE);
+
+    let buffer = vec![1u8; 1024 * 1024];
+    assert!(allocator::committed() >= before + buffer.len());
+    assert_eq!(
+        buffer.iter().map(|&b| b as usize).sum::<usize>(),
+        buffer.len()
+    );
+}
+
+// the other allocators get the whole heap up front
+#[cfg(not(any(feature = "bump-allocator", feature = "linked-list-allocator")))]
+#[test_case]
+fn grows_and_gives_back_pages() {
+    let size = allocator::size();
+    let buffer = vec![1u8; 4 * allocator::HEAP_INITIAL_SIZE];
+    assert!(allocator::size() >= size + buffer.len());
+
+    let committed = allocator::committed();
+    drop(buffer);
+    assert!(allocator::committed() < committed);
+}
+
+#[test_case]
+fn counts_allocations() {
+    let before = allocator::stats();
+    let x = Box::new(41u64);
+    let stats = allocator::stats();
+    assert_eq!(stats.total.live(), before.total.live() + 1);
+    assert_eq!(
+        stats.classes[0].allocations,
+        before.classes[0].allocations + 1
+    );
+    assert!(stats.peak_bytes >= stats.total.bytes);
+
+    drop(x);
+    assert_eq!(allocator::stats().total.live(), before.total.live());
+}
+
+#[test_case]
+fn reports_live_allocations() {
+    use allocator::tracking;
+
+    tracking::start();
+    let checkpoint = tracking::checkpoint();
+    let leaked = {
+        let _site = tracking::site("test");
+        let _freed = Box::new(1u32);
+        Box::new(2u32)
+    };
+    let live = tracking::live_since(checkpoint);
+    tracking::stop();
+
+    assert_eq!(live.len(), 1);
+    assert_eq!(live[0].addr, &*leaked as *const u32 as usize);
+    assert_eq!(live[0].site, "test");
+}
+
+#[test_case]
+fn try_reserve_fails_without_panicking() {
+    use kernel::pmem::PmemError;
+
+    let mut vec: Vec<u8> = Vec::new();
+    let err = vec.try_reserve(HEAP_SIZE).unwrap_err();
+    assert_eq!(PmemError::from(err), PmemError::OutOfMemory);
+}
+
+#[test_case]
+fn try_spawn_fails_with_full_queue() {
+    use kernel::task::executor::{Executor, SpawnError};
+    use kernel::task::Task;
+
+    // the queue holds 100 tasks, which only run once the executor does
+    let mut executor = Executor::new();
+    for _ in 0..100 {
+        assert!(executor.try_spawn(Task::new(async {})).is_ok());
+    }
+    let res = executor.try_spawn(Task::new(async {}));
+    assert!(matches!(res, Err(SpawnError::QueueFull)));
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     kernel::test_panic_handler(info)
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::borrow::ToOwned;
+use bootloader_api::info::MemoryRegionKind;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::allocator;
+use kernel::iomem::{Kind, MemoryMap};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::memory;
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn nested_resources_dont_conflict() {
+    let mut map = MemoryMap::new();
+    map.insert(0x0..0x10_0000, Kind::Usable);
+    map.insert(0x1000..0x2000, Kind::Heap);
+    map.insert(0x2000..0x4000, Kind::FreeFrames);
+    map.insert(
+        0x10_0000..0x20_0000,
+        Kind::Reserved(MemoryRegionKind::UnknownBios(7)),
+    );
+    map.insert(0x10_0000..0x20_0000, Kind::Nvdimm(1));
+    map.insert(0x11_0000..0x12_0000, Kind::Pool("a".to_owned()));
+    assert!(map.conflicts().is_empty());
+
+    map.insert(0x3000..0x5000, Kind::Heap);
+    map.insert(0x1f_0000..0x21_0000, Kind::Pool("b".to_owned()));
+    assert_eq!(map.conflicts().len(), 3);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::pmem::{get_legacy_devices, NfitDevice, Persistence};
+use x86_64::PhysAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory;
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+fn region(start: u64, end: u64, kind: MemoryRegionKind) -> MemoryRegion {
+    MemoryRegion { start, end, kind }
+}
+
+#[test_case]
+fn persistent_types_become_devices() {
+    let regions = [
+        region(0, 0x9_f000, MemoryRegionKind::Usable),
+        region(
+            0x1_0000_0000,
+            0x1_4000_0000,
+            MemoryRegionKind::UnknownBios(7),
+        ),
+        region(
+            0x2_0000_0800,
+            0x2_1000_0000,
+            MemoryRegionKind::UnknownBios(12),
+        ),
+        region(
+            0x3_0000_0000,
+            0x3_1000_0000,
+            MemoryRegionKind::UnknownUefi(14),
+        ),
+        // reserved
+        region(
+            0x4_0000_0000,
+            0x4_1000_0000,
+            MemoryRegionKind::UnknownBios(2),
+        ),
+        // too small to hold more than the pool table
+        region(
+            0x5_0000_0000,
+            0x5_0000_1000,
+            MemoryRegionKind::UnknownBios(12),
+        ),
+    ];
+
+    let devices = get_legacy_devices(&regions, &[]);
+    let found: Vec<_> = devices
+        .iter()
+        .map(|d| (d.phys_addr.as_u64(), d.size))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (0x1_0000_0000, 0x4000_0000),
+            (0x2_0000_1000, 0x0fff_f000),
+            (0x3_0000_0000, 0x1000_0000),
+        ]
+    );
+
+    for (i, device) in devices.iter().enumerate() {
+        assert_eq!(device.persistence, Persistence::Unknown);
+        assert_eq!(device.handle, devices[0].handle + i as u32);
+        assert!(device.flush_addresses.is_none());
+    }
+}
+
+#[test_case]
+fn regions_described_by_nfit_are_skipped() {
+    let regions = [
+        region(
+            0x1_0000_0000,
+            0x1_4000_0000,
+            MemoryRegionKind::UnknownBios(12),
+        ),
+        region(
+            0x2_0000_0000,
+            0x2_4000_0000,
+            MemoryRegionKind::UnknownBios(12),
+        ),
+    ];
+    let nfit_devices = [NfitDevice {
+        handle: 1,
+        phys_addr: PhysAddr::new(0x1_0000_0000),
+        size: 0x4000_0000,
+        ..NfitDevice::default()
+    }];
+
+    let devices = get_legacy_devices(&regions, &nfit_devices);
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].phys_addr.as_u64(), 0x2_0000_0000);
+    assert_ne!(devices[0].handle, nfit_devices[0].handle);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
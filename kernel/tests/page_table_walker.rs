@@ -0,0 +1,156 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::memory::FRAMES;
+use kernel::vmem::{PageTableWalker, Pages};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTable, PageTableFlags as Flags,
+    PhysFrame,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    PHYS_OFFSET.store(phys_mem_offset.as_u64(), Ordering::Relaxed);
+    unsafe {
+        FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+const GIB: u64 = 1 << 30;
+const MIB_2: u64 = 2 << 20;
+/// Start of the second level 4 entry, where the test tables map.
+const BASE: u64 = 1 << 39;
+
+const HUGE: u64 = BASE + 0x1234_5678;
+const LARGE: u64 = BASE + GIB + 0x1_2345;
+const REGULAR: u64 = BASE + GIB + MIB_2 + 0x3abc;
+
+/// Builds page tables that are never loaded, with a 1 GiB, a 2 MiB and a
+/// 4 KiB page below tables with differing flags.
+fn build_tables() -> [PhysFrame; 4] {
+    let frames = [(); 4].map(|_| {
+        let frame = FRAMES.lock().allocate_frame().unwrap();
+        table(frame).zero();
+        frame
+    });
+    let [p4, p3, p2, p1] = frames;
+
+    let table_flags = Flags::PRESENT | Flags::WRITABLE | Flags::USER_ACCESSIBLE;
+    table(p4)[1].set_frame(p3, table_flags);
+    table(p3)[0].set_addr(
+        PhysAddr::new(GIB),
+        Flags::PRESENT | Flags::WRITABLE | Flags::HUGE_PAGE,
+    );
+    table(p3)[1].set_frame(p2, table_flags);
+    table(p2)[0].set_addr(
+        PhysAddr::new(MIB_2),
+        Flags::PRESENT | Flags::USER_ACCESSIBLE | Flags::HUGE_PAGE,
+    );
+    table(p2)[1].set_frame(p1, Flags::PRESENT | Flags::NO_EXECUTE);
+    table(p1)[3].set_addr(
+        PhysAddr::new(0x5000),
+        Flags::PRESENT | Flags::WRITABLE | Flags::USER_ACCESSIBLE,
+    );
+
+    frames
+}
+
+fn free_tables(frames: [PhysFrame; 4]) {
+    for frame in frames {
+        unsafe { FRAMES.lock().deallocate_frame(frame) };
+    }
+}
+
+fn phys_offset() -> VirtAddr {
+    VirtAddr::new(PHYS_OFFSET.load(Ordering::Relaxed))
+}
+
+fn table(frame: PhysFrame) -> &'static mut PageTable {
+    let addr = phys_offset() + frame.start_address().as_u64();
+    unsafe { &mut *addr.as_mut_ptr() }
+}
+
+#[test_case]
+fn translates_all_page_sizes() {
+    let frames = build_tables();
+    let mut mapper = unsafe { OffsetPageTable::new(table(frames[0]), phys_offset()) };
+    let walker = PageTableWalker::new(&mut mapper);
+
+    let translate = |addr| walker.translate(VirtAddr::new(addr)).map(PhysAddr::as_u64);
+    assert_eq!(translate(HUGE), Some(GIB + 0x1234_5678));
+    assert_eq!(translate(LARGE), Some(MIB_2 + 0x1_2345));
+    assert_eq!(translate(REGULAR), Some(0x5abc));
+    assert_eq!(translate(REGULAR + 0x1000), None);
+    assert_eq!(translate(BASE + GIB + 2 * MIB_2), None);
+    assert_eq!(translate(BASE + 2 * GIB), None);
+    assert_eq!(translate(0), None);
+
+    let pages: Vec<_> = walker.iter().map(|m| m.virt).collect();
+    assert_eq!(
+        pages,
+        [
+            Pages::Huge(Page::containing_address(VirtAddr::new(HUGE))),
+            Pages::Large(Page::containing_address(VirtAddr::new(LARGE))),
+            Pages::Regular(Page::containing_address(VirtAddr::new(REGULAR))),
+        ]
+    );
+    let range = VirtAddr::new(BASE + GIB)..VirtAddr::new(BASE + GIB + 2 * MIB_2);
+    assert_eq!(walker.mappings(range).count(), 2);
+
+    free_tables(frames);
+}
+
+#[test_case]
+fn combines_flags_of_all_levels() {
+    let frames = build_tables();
+    let mut mapper = unsafe { OffsetPageTable::new(table(frames[0]), phys_offset()) };
+    let walker = PageTableWalker::new(&mut mapper);
+
+    let flags = |addr| walker.flags(VirtAddr::new(addr)).unwrap();
+
+    // only the leaf leaves out user access
+    assert!(flags(HUGE).contains(Flags::WRITABLE));
+    assert!(!flags(HUGE).contains(Flags::USER_ACCESSIBLE));
+    assert!(!flags(HUGE).contains(Flags::NO_EXECUTE));
+
+    // only the leaf leaves out writing
+    assert!(!flags(LARGE).contains(Flags::WRITABLE));
+    assert!(flags(LARGE).contains(Flags::USER_ACCESSIBLE));
+
+    // the table restricts a leaf that allows everything
+    assert!(!flags(REGULAR).contains(Flags::WRITABLE));
+    assert!(!flags(REGULAR).contains(Flags::USER_ACCESSIBLE));
+    assert!(flags(REGULAR).contains(Flags::NO_EXECUTE));
+
+    assert_eq!(walker.flags(VirtAddr::new(REGULAR + 0x1000)), None);
+
+    free_tables(frames);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
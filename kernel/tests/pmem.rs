@@ -0,0 +1,409 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+use bootloader_api::{entry_point, BootInfo};
+use core::ffi::{c_char, c_int, c_long, c_void};
+use core::panic::PanicInfo;
+use core::ptr;
+use embedded_io::SeekFrom;
+use kernel::errno;
+use kernel::pmem::fd::{FileTable, MAX_OPEN};
+use kernel::pmem::file::{OpenOptions, PoolFile};
+use kernel::pmem::{self, PmemError};
+
+entry_point!(main);
+
+/// Size of the device the tests create their pools on.
+const DEVICE_FRAMES: u64 = 2048;
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory;
+    use kernel::vmem::{self, MappedRegions, Owner, UsableRegions};
+    use x86_64::structures::paging::{PageSize, Size4KiB};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    let usable = vmem::get_mappings(&mut mapper).into_regions().into_usable();
+    let mut page_allocator = vmem::Manager::new(mapper, &memory::FRAMES, usable);
+    let heap_start = VirtAddr::new(allocator::HEAP_START as u64);
+    page_allocator.register(heap_start..(heap_start + allocator::HEAP_SIZE), Owner::Heap);
+    vmem::MANAGER.lock().set(page_allocator).unwrap();
+
+    // ordinary memory posing as a legacy device, the tests don't reboot
+    let frames = memory::FRAMES
+        .lock()
+        .allocate_contiguous(DEVICE_FRAMES, Size4KiB::SIZE)
+        .unwrap();
+    let start = frames.start.start_address().as_u64();
+    let table: *mut u8 = (phys_mem_offset + start).as_mut_ptr();
+    unsafe { table.write_bytes(0, Size4KiB::SIZE as usize) };
+    let regions = [MemoryRegion {
+        start,
+        end: frames.end.start_address().as_u64(),
+        kind: MemoryRegionKind::UnknownBios(12),
+    }];
+    unsafe { pmem::MANAGER.lock().init(None, &regions) };
+
+    test_main();
+    loop {}
+}
+
+fn contents(name: &str) -> Vec<u8> {
+    let mut file = PoolFile::open(name).unwrap();
+    let mut buf = vec![0; file.len().unwrap() as usize];
+    assert_eq!(file.read(&mut buf), Ok(buf.len()));
+    buf
+}
+
+#[test_case]
+fn append_writes_at_end() {
+    let mut file = PoolFile::create("append").unwrap();
+    file.write(b"abc").unwrap();
+    drop(file);
+
+    let mut file = OpenOptions::new().append(true).open("append").unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.write(b"def").unwrap();
+    assert_eq!(file.position(), 6);
+    drop(file);
+
+    assert_eq!(contents("append"), b"abcdef");
+    pmem::MANAGER.lock().destroy_pool("append").unwrap();
+}
+
+#[test_case]
+fn write_past_end_zeroes_hole() {
+    let mut file = PoolFile::create("hole").unwrap();
+    file.write(&[0xff; 12]).unwrap();
+    file.set_len(2).unwrap();
+
+    file.seek(SeekFrom::Start(10)).unwrap();
+    file.write(b"cd").unwrap();
+    assert_eq!(file.len(), Ok(12));
+    drop(file);
+
+    assert_eq!(contents("hole"), b"\xff\xff\0\0\0\0\0\0\0\0cd");
+    pmem::MANAGER.lock().destroy_pool("hole").unwrap();
+}
+
+#[test_case]
+fn truncate_on_open() {
+    let mut file = PoolFile::create("truncate").unwrap();
+    file.write(b"hello").unwrap();
+    drop(file);
+
+    let file = OpenOptions::new().write(true).open("truncate").unwrap();
+    assert_eq!(file.len(), Ok(5));
+    drop(file);
+
+    let file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open("truncate")
+        .unwrap();
+    assert_eq!(file.len(), Ok(0));
+    drop(file);
+
+    assert_eq!(
+        OpenOptions::new().truncate(true).open("truncate").err(),
+        Some(PmemError::NotWritable)
+    );
+    pmem::MANAGER.lock().destroy_pool("truncate").unwrap();
+}
+
+extern "C" {
+    fn fopen(filename: *const c_char, mode: *const c_char) -> *mut c_void;
+    fn fread(buf: *mut c_void, size: usize, count: usize, file: *mut c_void) -> usize;
+    fn fwrite(buf: *const c_void, size: usize, count: usize, file: *mut c_void) -> usize;
+    fn fseek(file: *mut c_void, offset: c_long, whence: c_int) -> c_int;
+    fn ftell(file: *mut c_void) -> c_long;
+    fn rewind(file: *mut c_void);
+    fn feof(file: *mut c_void) -> c_int;
+    fn fclose(file: *mut c_void) -> c_int;
+    fn rename(old_filename: *const c_char, new_filename: *const c_char) -> c_int;
+    fn truncate(filename: *const c_char, length: c_long) -> c_int;
+    fn fileno(file: *mut c_void) -> c_int;
+    fn mmap(
+        addr: *mut c_void,
+        length: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: c_long,
+    ) -> *mut c_void;
+    fn close(fd: c_int) -> c_int;
+}
+
+const SEEK_SET: c_int = 0;
+const SEEK_END: c_int = 2;
+
+/// Opens a pool like C code does, `name` and `mode` are NUL-terminated.
+fn open(name: &[u8], mode: &[u8]) -> *mut c_void {
+    let file = unsafe { fopen(name.as_ptr().cast(), mode.as_ptr().cast()) };
+    assert!(!file.is_null());
+    file
+}
+
+fn read(file: *mut c_void, buf: &mut [u8]) -> usize {
+    unsafe { fread(buf.as_mut_ptr().cast(), 1, buf.len(), file) }
+}
+
+fn write(file: *mut c_void, buf: &[u8]) -> usize {
+    unsafe { fwrite(buf.as_ptr().cast(), 1, buf.len(), file) }
+}
+
+#[test_case]
+fn failed_calls_set_errno() {
+    let open_read = |name: &[u8]| unsafe { fopen(name.as_ptr().cast(), b"r\0".as_ptr().cast()) };
+
+    assert_eq!(open_read(b"missing\0"), ptr::null_mut());
+    assert_eq!(errno::get(), errno::ENOENT);
+
+    assert_eq!(open_read(b"\0"), ptr::null_mut());
+    assert_eq!(errno::get(), errno::EINVAL);
+
+    let mut long = [b'a'; 1024];
+    long[1023] = 0;
+    assert_eq!(open_read(&long), ptr::null_mut());
+    assert_eq!(errno::get(), errno::ENAMETOOLONG);
+
+    assert_eq!(unsafe { close(1000) }, -1);
+    assert_eq!(errno::get(), errno::EBADF);
+    assert_eq!(unsafe { close(-1) }, -1);
+    assert_eq!(errno::get(), errno::EBADF);
+}
+
+#[test_case]
+fn stdio_tracks_position() {
+    let file = open(b"stdio\0", b"w+\0");
+    assert_eq!(write(file, b"hello world"), 11);
+    assert_eq!(unsafe { ftell(file) }, 11);
+
+    assert_eq!(unsafe { fseek(file, 6, SEEK_SET) }, 0);
+    let mut buf = [0; 3];
+    assert_eq!(read(file, &mut buf), 3);
+    assert_eq!(&buf, b"wor");
+    assert_eq!(unsafe { ftell(file) }, 9);
+    assert_eq!(write(file, b"LD"), 2);
+    assert_eq!(unsafe { ftell(file) }, 11);
+
+    assert_eq!(unsafe { fseek(file, -5, SEEK_END) }, 0);
+    let mut buf = [0; 5];
+    assert_eq!(read(file, &mut buf), 5);
+    assert_eq!(&buf, b"worLD");
+    // whole items only
+    assert_eq!(unsafe { fseek(file, 6, SEEK_SET) }, 0);
+    assert_eq!(unsafe { fread(buf.as_mut_ptr().cast(), 2, 2, file) }, 2);
+    assert_eq!(unsafe { fread(buf.as_mut_ptr().cast(), 2, 1, file) }, 0);
+
+    assert_eq!(unsafe { fclose(file) }, 0);
+    pmem::MANAGER.lock().destroy_pool("stdio").unwrap();
+}
+
+#[test_case]
+fn rewind_clears_eof() {
+    PoolFile::create("eof").unwrap().write(b"abc").unwrap();
+    let file = open(b"eof\0", b"r\0");
+
+    let mut buf = [0; 4];
+    assert_eq!(read(file, &mut buf), 3);
+    assert_ne!(unsafe { feof(file) }, 0);
+    assert_eq!(read(file, &mut buf), 0);
+    assert_ne!(unsafe { feof(file) }, 0);
+
+    unsafe { rewind(file) };
+    assert_eq!(unsafe { feof(file) }, 0);
+    assert_eq!(unsafe { ftell(file) }, 0);
+    assert_eq!(read(file, &mut buf[..3]), 3);
+    assert_eq!(unsafe { feof(file) }, 0);
+    assert_eq!(&buf[..3], b"abc");
+
+    assert_eq!(unsafe { fclose(file) }, 0);
+    pmem::MANAGER.lock().destroy_pool("eof").unwrap();
+}
+
+#[test_case]
+fn stdio_appends_at_end() {
+    PoolFile::create("stdio_append")
+        .unwrap()
+        .write(b"abc")
+        .unwrap();
+    let file = open(b"stdio_append\0", b"a+\0");
+
+    assert_eq!(unsafe { fseek(file, 0, SEEK_SET) }, 0);
+    assert_eq!(write(file, b"def"), 3);
+    assert_eq!(unsafe { ftell(file) }, 6);
+
+    unsafe { rewind(file) };
+    let mut buf = [0; 6];
+    assert_eq!(read(file, &mut buf), 6);
+    assert_eq!(&buf, b"abcdef");
+
+    assert_eq!(unsafe { fclose(file) }, 0);
+    pmem::MANAGER.lock().destroy_pool("stdio_append").unwrap();
+}
+
+#[test_case]
+fn rename_replaces_target() {
+    PoolFile::create("from").unwrap().write(b"new").unwrap();
+    PoolFile::create("to").unwrap().write(b"old pool").unwrap();
+
+    let rename_pool =
+        |from: &[u8], to: &[u8]| unsafe { rename(from.as_ptr().cast(), to.as_ptr().cast()) };
+    assert_eq!(rename_pool(b"from\0", b"to\0"), 0);
+    assert_eq!(contents("to"), b"new");
+    assert_eq!(
+        pmem::MANAGER.lock().find_pool("from").err(),
+        Some(PmemError::NotFound)
+    );
+
+    assert_eq!(rename_pool(b"from\0", b"to\0"), -1);
+    assert_eq!(errno::get(), errno::ENOENT);
+    pmem::MANAGER.lock().destroy_pool("to").unwrap();
+}
+
+#[test_case]
+fn truncate_returns_status() {
+    PoolFile::create("resize").unwrap().write(b"abc").unwrap();
+    let resize = |name: &[u8], length| unsafe { truncate(name.as_ptr().cast(), length) };
+
+    assert_eq!(resize(b"resize\0", 5), 0);
+    assert_eq!(contents("resize"), b"abc\0\0");
+    assert_eq!(resize(b"resize\0", 1), 0);
+    assert_eq!(contents("resize"), b"a");
+
+    assert_eq!(resize(b"resize\0", -1), -1);
+    assert_eq!(errno::get(), errno::EINVAL);
+    assert_eq!(resize(b"missing\0", 0), -1);
+    assert_eq!(errno::get(), errno::ENOENT);
+    pmem::MANAGER.lock().destroy_pool("resize").unwrap();
+}
+
+#[test_case]
+fn mmap_stays_within_pool() {
+    const PAGE: usize = 4096;
+    let mut pool = PoolFile::create("mmap").unwrap();
+    pool.set_len(2 * PAGE as u64).unwrap();
+    let base = unsafe { pool.map() }.unwrap().as_mut_ptr();
+    drop(pool);
+
+    let file = open(b"mmap\0", b"r+\0");
+    let fd = unsafe { fileno(file) };
+    let map = |length, offset| unsafe { mmap(ptr::null_mut(), length, 0, 0, fd, offset) };
+    let failed = !0 as *mut c_void;
+
+    assert_eq!(map(2 * PAGE, 0), base.cast());
+    assert_eq!(map(PAGE, PAGE as c_long), unsafe { base.add(PAGE) }.cast());
+
+    assert_eq!(map(PAGE + 1, PAGE as c_long), failed);
+    assert_eq!(errno::get(), errno::EINVAL);
+    assert_eq!(map(1, 2 * PAGE as c_long), failed);
+    assert_eq!(errno::get(), errno::EINVAL);
+    assert_eq!(map(usize::MAX, PAGE as c_long), failed);
+    assert_eq!(errno::get(), errno::EINVAL);
+
+    assert_eq!(unsafe { fclose(file) }, 0);
+    pmem::MANAGER.lock().destroy_pool("mmap").unwrap();
+}
+
+#[test_case]
+fn lowest_free_descriptor_is_used() {
+    PoolFile::create("lowest").unwrap();
+    let open = || PoolFile::open("lowest").unwrap();
+    let mut files = FileTable::new();
+
+    assert_eq!(files.insert(open()), Ok(0));
+    assert_eq!(files.insert(open()), Ok(1));
+    assert_eq!(files.insert(open()), Ok(2));
+    files.close(1).unwrap();
+    files.close(0).unwrap();
+    assert_eq!(files.insert(open()), Ok(0));
+    assert_eq!(files.dup(2), Ok(1));
+
+    assert_eq!(files.close(1), Ok(()));
+    assert_eq!(files.close(1), Err(PmemError::BadDescriptor));
+    assert_eq!(files.close(5), Err(PmemError::BadDescriptor));
+    assert!(files.get(1).is_err());
+
+    drop(files);
+    pmem::MANAGER.lock().destroy_pool("lowest").unwrap();
+}
+
+#[test_case]
+fn duplicates_share_open_file() {
+    PoolFile::create("dup").unwrap().write(b"abcdef").unwrap();
+    let mut files = FileTable::new();
+    let fd = files.insert(PoolFile::open("dup").unwrap()).unwrap();
+
+    let dup = files.dup(fd).unwrap();
+    assert_ne!(dup, fd);
+    let mut buf = [0; 3];
+    files.get(fd).unwrap().lock().file.read(&mut buf).unwrap();
+    files.get(dup).unwrap().lock().file.read(&mut buf).unwrap();
+    assert_eq!(&buf, b"def");
+
+    // replaces an open descriptor, or grows the table
+    let other = files.insert(PoolFile::open("dup").unwrap()).unwrap();
+    assert_eq!(files.dup2(fd, other), Ok(other));
+    assert_eq!(files.get(other).unwrap().lock().file.position(), 6);
+    assert_eq!(files.dup2(fd, 10), Ok(10));
+    assert!(Arc::ptr_eq(
+        &files.get(10).unwrap(),
+        &files.get(fd).unwrap()
+    ));
+    assert_eq!(files.dup2(fd, fd), Ok(fd));
+    assert_eq!(files.dup2(fd, MAX_OPEN), Err(PmemError::BadDescriptor));
+    assert_eq!(files.dup2(7, 8), Err(PmemError::BadDescriptor));
+
+    files.close(fd).unwrap();
+    assert!(files.get(dup).is_ok());
+
+    drop(files);
+    pmem::MANAGER.lock().destroy_pool("dup").unwrap();
+}
+
+#[test_case]
+fn open_descriptors_are_limited() {
+    PoolFile::create("limit").unwrap();
+    let mut files = FileTable::new();
+    let fd = files.insert(PoolFile::open("limit").unwrap()).unwrap();
+
+    for i in 1..MAX_OPEN {
+        assert_eq!(files.dup(fd), Ok(i));
+    }
+    assert_eq!(files.dup(fd), Err(PmemError::TooManyOpenFiles));
+    assert_eq!(
+        files.insert(PoolFile::open("limit").unwrap()),
+        Err(PmemError::TooManyOpenFiles)
+    );
+
+    files.close(100).unwrap();
+    assert_eq!(files.dup(fd), Ok(100));
+
+    drop(files);
+    pmem::MANAGER.lock().destroy_pool("limit").unwrap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use bootloader_api::{entry_point, BootInfo};
+use core::cell::Cell;
+use core::future::{self, Future};
+use core::panic::PanicInfo;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::FutureExt;
+use kernel::allocator;
+use kernel::task::join::JoinError;
+use kernel::task::simple_executor::SimpleExecutor;
+use kernel::task::Task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::memory;
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Polls `future` whenever it was woken, so a lost wakeup hangs the test.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let woken = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(woken.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        while !woken.0.swap(false, Ordering::Relaxed) {
+            x86_64::instructions::hlt();
+        }
+    }
+}
+
+#[test_case]
+fn join_handle_returns_output() {
+    let mut executor = SimpleExecutor::new();
+    let handle = executor.spawn(Task::new(async { 42 }));
+    let joined = Rc::new(Cell::new(None));
+    let result = joined.clone();
+    executor.spawn(Task::new(async move { result.set(Some(handle.await)) }));
+    executor.run();
+
+    assert_eq!(joined.get(), Some(Ok(42)));
+}
+
+#[test_case]
+fn abort_drops_task() {
+    let mut executor = SimpleExecutor::new();
+    let handle = executor.spawn(Task::new(future::pending::<()>()));
+    assert!(!handle.is_finished());
+    handle.abort();
+    executor.run();
+
+    assert!(handle.is_finished());
+    assert_eq!(handle.now_or_never(), Some(Err(JoinError::Aborted)));
+}
+
+#[test_case]
+fn abort_wakes_awaiting_task() {
+    let mut executor = SimpleExecutor::new();
+    let mut handle = executor.spawn(Task::new(future::pending::<()>()));
+    let mut aborted = false;
+    let joined = block_on(future::poll_fn(|cx| {
+        let poll = Pin::new(&mut handle).poll(cx);
+        if !aborted {
+            // only the waker registered by the poll above wakes us again
+            handle.abort();
+            aborted = true;
+        }
+        poll
+    }));
+    assert_eq!(joined, Err(JoinError::Aborted));
+
+    executor.run();
+    assert!(handle.is_finished());
+}
+
+#[test_case]
+fn dropped_handle_detaches_task() {
+    let mut executor = SimpleExecutor::new();
+    let ran = Rc::new(Cell::new(false));
+    let flag = ran.clone();
+    drop(executor.spawn(Task::new(async move { flag.set(true) })));
+    executor.run();
+
+    assert!(ran.get());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use bootloader_api::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use kernel::allocator;
+use kernel::task::timer::{self, Elapsed};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::memory;
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    unsafe {
+        memory::FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Polls `future` whenever it was woken, so a lost wakeup hangs the test.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let woken = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(woken.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        while !woken.0.swap(false, Ordering::Relaxed) {
+            x86_64::instructions::hlt();
+        }
+    }
+}
+
+#[test_case]
+fn ticks_advance() {
+    let start = timer::ticks();
+    while timer::ticks() < start + 10 {
+        x86_64::instructions::hlt();
+    }
+    assert!(timer::uptime() >= Duration::from_millis(10));
+}
+
+#[test_case]
+fn sleep_waits() {
+    let start = timer::ticks();
+    block_on(timer::sleep(Duration::from_millis(20)));
+    assert!(timer::ticks() - start >= 20);
+}
+
+#[test_case]
+fn interval_yields_every_period() {
+    block_on(async {
+        let mut interval = timer::interval(Duration::from_millis(5));
+        let first = interval.tick().await;
+        assert_eq!(interval.tick().await, first + 5);
+        assert_eq!(interval.tick().await, first + 10);
+    });
+}
+
+#[test_case]
+fn timeout_elapses() {
+    let slow = timer::timeout(
+        timer::sleep(Duration::from_secs(10)),
+        Duration::from_millis(10),
+    );
+    assert_eq!(block_on(slow), Err(Elapsed));
+
+    let fast = timer::timeout(async { 42 }, Duration::from_millis(10));
+    assert_eq!(block_on(fast), Ok(42));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
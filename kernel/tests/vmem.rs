@@ -0,0 +1,139 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::allocator::{HEAP_SIZE, HEAP_START};
+use kernel::memory::{self, BitmapFrameAllocator, FRAMES};
+use kernel::vmem::{self, Attributes, MappedRegions, Owner, UsableRegions};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Page, PageSize, PhysFrame, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    PHYS_OFFSET.store(phys_mem_offset.as_u64(), Ordering::Relaxed);
+    unsafe {
+        FRAMES
+            .lock()
+            .init(&boot_info.memory_regions, phys_mem_offset);
+        allocator::init_heap(phys_mem_offset);
+    }
+
+    test_main();
+    loop {}
+}
+
+/// Creates a manager of the active page tables, like the kernel does.
+fn manager() -> vmem::Manager<'static, BitmapFrameAllocator> {
+    let mut mapper = unsafe { memory::init(VirtAddr::new(PHYS_OFFSET.load(Ordering::Relaxed))) };
+    let usable = vmem::get_mappings(&mut mapper).into_regions().into_usable();
+
+    let mut manager = vmem::Manager::new(mapper, &FRAMES, usable);
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    manager.register(heap_start..(heap_start + HEAP_SIZE), Owner::Heap);
+    manager
+}
+
+/// More pages than a level 1 table holds.
+const PAGES: u64 = 600;
+
+#[test_case]
+fn deallocating_frees_frames_and_tables() {
+    let mut manager = manager();
+    // a level 3 table made on the way stays, so the first round may keep one
+    let pages = manager
+        .allocate_anonymous(PAGES, Attributes::new(), Owner::Heap)
+        .unwrap();
+    assert!(manager.deallocate(pages));
+    let free = FRAMES.lock().free_frames();
+
+    let pages = manager
+        .allocate_anonymous(PAGES, Attributes::new(), Owner::Heap)
+        .unwrap();
+    // the frames and at least one level 1 table of its own
+    assert!(FRAMES.lock().free_frames() < free - PAGES);
+    assert!(manager.deallocate(pages));
+
+    assert_eq!(FRAMES.lock().free_frames(), free);
+    assert_eq!(manager.translate(pages.start.start_address()), None);
+}
+
+#[test_case]
+fn shrinking_frees_trailing_frames() {
+    let mut manager = manager();
+    let pages = manager
+        .allocate_anonymous(PAGES, Attributes::new(), Owner::Heap)
+        .unwrap();
+    assert!(manager.deallocate(pages));
+    let free = FRAMES.lock().free_frames();
+
+    let pages = manager
+        .allocate_anonymous(PAGES, Attributes::new(), Owner::Heap)
+        .unwrap();
+    let allocated = FRAMES.lock().free_frames();
+    let kept = Page::range(pages.start, pages.start + 10);
+    let trailing = Page::range(kept.end, pages.end);
+
+    // only the end of an allocation can be given back
+    assert!(!manager.deallocate(kept));
+    assert!(manager.deallocate(trailing));
+    assert!(FRAMES.lock().free_frames() >= allocated + (PAGES - 10));
+    assert!(manager.translate(kept.start.start_address()).is_some());
+    assert!(manager.translate((kept.end - 1).start_address()).is_some());
+    assert_eq!(manager.translate(trailing.start.start_address()), None);
+
+    assert!(manager.deallocate(kept));
+    assert_eq!(FRAMES.lock().free_frames(), free);
+}
+
+#[test_case]
+fn guard_pages_surround_guarded_allocation() {
+    let mut manager = manager();
+    let frame: PhysFrame = FRAMES.lock().allocate_frame().unwrap();
+    let pages = manager
+        .allocate_guarded::<Size4KiB>(frame.start_address(), 1, Attributes::new(), Owner::Mmio)
+        .unwrap();
+    let start = pages.start.start_address();
+    let end = pages.end.start_address();
+
+    assert_eq!(manager.translate(start), Some(frame.start_address()));
+    let allocation = manager.allocation(start).unwrap();
+    assert_eq!(allocation.owner, Owner::Mmio);
+    assert!(!allocation.is_guard(start.as_u64()));
+
+    for guard in [
+        start - Size4KiB::SIZE,
+        start - 1u64,
+        end,
+        end + (Size4KiB::SIZE - 1),
+    ] {
+        assert_eq!(manager.translate(guard), None);
+        let allocation = manager.allocation(guard).unwrap();
+        assert_eq!(allocation.owner, Owner::Mmio);
+        assert!(allocation.is_guard(guard.as_u64()));
+    }
+    assert!(manager.allocation(end + Size4KiB::SIZE).is_none());
+
+    assert!(manager.deallocate(pages));
+    assert!(manager.allocation(start).is_none());
+    assert!(manager.allocation(end).is_none());
+    unsafe { FRAMES.lock().deallocate_frame(frame) };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,154 @@
+//! Runs the allocator conformance suite on the host. The kernel can't be
+//! built for the host, so the allocators are included from its sources and
+//! this crate stands in for their parent module.
+
+// like the kernel, for the allocators' const constructors
+#![feature(const_mut_refs)]
+// the included modules are only partly used
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use conformance::HEAP_SIZE;
+use debug::{DebugAllocator, HeapError};
+use std::alloc::{alloc, GlobalAlloc, Layout};
+use std::ops::Range;
+
+#[path = "../kernel/src/allocator/bump.rs"]
+mod bump;
+#[path = "../kernel/src/allocator/conformance.rs"]
+mod conformance;
+#[path = "../kernel/src/allocator/debug.rs"]
+mod debug;
+#[path = "../kernel/src/allocator/fixed_size_block.rs"]
+mod fixed_size_block;
+#[path = "../kernel/src/allocator/linked_list.rs"]
+mod linked_list;
+#[path = "../kernel/src/allocator/stats.rs"]
+mod stats;
+#[path = "../kernel/src/allocator/tracking.rs"]
+mod tracking;
+
+use std::println;
+
+/// Like the kernel's, as its allocator module can't be included.
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<A> {
+        self.inner.lock()
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// The host has no heap pages to give back.
+fn decommit(_range: Range<usize>) {}
+
+/// Leaks memory for an allocator under test.
+fn heap() -> usize {
+    let layout = Layout::from_size_align(HEAP_SIZE, 4096).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    assert!(!ptr.is_null());
+    ptr as usize
+}
+
+#[test]
+fn bump_allocator() {
+    let allocator = Locked::new(bump::BumpAllocator::new());
+    unsafe { allocator.lock().init(heap(), HEAP_SIZE) };
+    conformance::run(&allocator);
+}
+
+#[test]
+fn linked_list_allocator() {
+    let allocator = Locked::new(linked_list::LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap(), HEAP_SIZE) };
+    conformance::run(&allocator);
+
+    // freed memory is merged again, including the padding of aligned blocks
+    assert_eq!(allocator.lock().stats().largest_free, Some(HEAP_SIZE));
+}
+
+#[test]
+fn linked_list_allocator_grows_in_place() {
+    let allocator = Locked::new(linked_list::LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap(), HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        assert_eq!(allocator.realloc(ptr, layout, 4096), ptr);
+        allocator.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap());
+    }
+}
+
+#[test]
+fn fixed_size_block_allocator() {
+    let allocator = fixed_size_block::FixedSizeBlockAllocator::new();
+    unsafe { allocator.init(heap(), HEAP_SIZE, HEAP_SIZE) };
+    conformance::run(&allocator);
+}
+
+#[test]
+fn fixed_size_block_allocator_releases_slabs() {
+    let allocator = fixed_size_block::FixedSizeBlockAllocator::new();
+    unsafe { allocator.init(heap(), HEAP_SIZE, HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    let blocks: Vec<_> = (0..10_000)
+        .map(|_| unsafe { allocator.alloc(layout) })
+        .collect();
+    assert!(allocator.stats().fallback.live() > 1);
+    for block in blocks {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+
+    // all but a spare slab are given back
+    assert_eq!(allocator.stats().fallback.live(), 1);
+}
+
+#[test]
+fn debug_allocator() {
+    let allocator = DebugAllocator::new(Locked::new(linked_list::LinkedListAllocator::new()));
+    unsafe { allocator.inner().lock().init(heap(), HEAP_SIZE) };
+    conformance::run(&allocator);
+    assert_eq!(allocator.errors(), 0);
+}
+
+#[test]
+fn debug_allocator_detects_corruption() {
+    let allocator = DebugAllocator::new(Locked::new(linked_list::LinkedListAllocator::new()));
+    unsafe { allocator.inner().lock().init(heap(), HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        ptr.add(24).write(0);
+        allocator.dealloc(ptr, layout);
+        assert_eq!(allocator.last_error(), Some(HeapError::Overflow));
+
+        allocator.dealloc(ptr, layout);
+        assert_eq!(allocator.last_error(), Some(HeapError::DoubleFree));
+
+        ptr.write(0);
+        allocator.dealloc(allocator.alloc(layout), layout);
+        assert_eq!(allocator.last_error(), Some(HeapError::UseAfterFree));
+    }
+    assert_eq!(allocator.errors(), 3);
+}
+
+#[test]
+fn system_allocator() {
+    conformance::run(&std::alloc::System);
+}
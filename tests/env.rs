@@ -0,0 +1,73 @@
+//! Runs the environment's storage format on the host, included from the
+//! kernel's sources like the allocators are.
+
+extern crate alloc;
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+
+#[path = "../kernel/src/env/format.rs"]
+mod format;
+
+fn vars(entries: &[(&str, &str)]) -> BTreeMap<String, CString> {
+    entries
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), CString::new(value).unwrap()))
+        .collect()
+}
+
+fn defaults() -> BTreeMap<String, CString> {
+    vars(&[("CPUS", "1"), ("VERIFY", "2")])
+}
+
+#[test]
+fn defaults_are_not_stored() {
+    assert_eq!(format::serialize(&defaults(), &defaults()), "");
+
+    let mut parsed = defaults();
+    format::parse(b"", &mut parsed);
+    assert_eq!(parsed, defaults());
+}
+
+#[test]
+fn unset_default_is_stored_as_bare_name() {
+    let vars = vars(&[("VERIFY", "2")]);
+    let content = format::serialize(&vars, &defaults());
+    assert_eq!(content, "CPUS\n");
+
+    let mut parsed = defaults();
+    format::parse(content.as_bytes(), &mut parsed);
+    assert_eq!(parsed, vars);
+}
+
+#[test]
+fn round_trip() {
+    let vars = vars(&[("CPUS", "4"), ("HOME", "/"), ("EMPTY", "")]);
+    let content = format::serialize(&vars, &defaults());
+    assert_eq!(content, "CPUS=4\nEMPTY=\nHOME=/\nVERIFY\n");
+
+    let mut parsed = defaults();
+    format::parse(content.as_bytes(), &mut parsed);
+    assert_eq!(parsed, vars);
+}
+
+#[test]
+fn value_may_contain_equals_sign() {
+    let mut parsed = defaults();
+    format::parse(b"OPTS=a=b\n", &mut parsed);
+    assert_eq!(parsed["OPTS"].to_str(), Ok("a=b"));
+}
+
+#[test]
+fn malformed_entries_are_skipped() {
+    let mut parsed = defaults();
+    format::parse(b"=1\nNUL=a\0b\nA\0B\n\nCPUS=3\n", &mut parsed);
+    assert_eq!(parsed, vars(&[("CPUS", "3"), ("VERIFY", "2")]));
+}
+
+#[test]
+fn unset_marker_for_unknown_name_is_ignored() {
+    let mut parsed = defaults();
+    format::parse(b"MISSING\n", &mut parsed);
+    assert_eq!(parsed, defaults());
+}
@@ -0,0 +1,118 @@
+//! Runs the virtual address space's region allocator on the host, included
+//! from the kernel's sources like the allocators are.
+
+extern crate alloc;
+
+use std::iter;
+use std::ops::Range;
+
+#[path = "../kernel/src/vmem/region.rs"]
+mod region;
+
+use region::{Fit, RegionAllocator};
+
+#[test]
+fn equally_sized_regions() {
+    let mut regions = RegionAllocator::with_regions(Fit::Best, [0..0x1000, 0x2000..0x3000]);
+    assert!(regions.reserve(0x1000, 0x1000).is_some());
+    assert!(regions.reserve(0x1000, 0x1000).is_some());
+    assert!(regions.reserve(0x1000, 0x1000).is_none());
+}
+
+#[test]
+fn coalescing() {
+    let mut regions = RegionAllocator::with_regions(Fit::First, iter::once(0..0x3000));
+    let a = regions.reserve(0x1000, 1).unwrap();
+    let b = regions.reserve(0x1000, 1).unwrap();
+    let c = regions.reserve(0x1000, 1).unwrap();
+
+    assert!(regions.release(a));
+    assert!(regions.release(c));
+    assert!(regions.release(b.clone()));
+    assert!(!regions.release(b));
+    assert!(regions.regions().eq(iter::once(0..0x3000)));
+}
+
+#[test]
+fn alignment() {
+    let mut regions = RegionAllocator::with_regions(Fit::First, iter::once(0x10..0x3000));
+    assert_eq!(regions.reserve(0x1000, 0x1000), Some(0x1000..0x2000));
+    assert!(regions.regions().eq([0x10..0x1000, 0x2000..0x3000]));
+}
+
+#[test]
+fn take() {
+    let mut regions = RegionAllocator::with_regions(Fit::First, iter::once(0..0x3000));
+    assert!(regions.take(0x1000..0x2000));
+    assert!(!regions.take(0x1800..0x2800));
+    assert!(!regions.take(0x2800..0x3800));
+    assert!(regions.regions().eq([0..0x1000, 0x2000..0x3000]));
+}
+
+#[test]
+fn random_first_fit() {
+    random_operations(Fit::First);
+}
+
+#[test]
+fn random_best_fit() {
+    random_operations(Fit::Best);
+}
+
+/// Reserves and releases random ranges and checks after every step that
+/// nothing overlaps and no space got lost.
+fn random_operations(fit: Fit) {
+    const TOTAL: u64 = 0x100_0000;
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut regions = RegionAllocator::with_regions(fit, [0..TOTAL / 2, TOTAL / 2..TOTAL]);
+    let mut reserved: Vec<Range<u64>> = Vec::new();
+
+    for _ in 0..2000 {
+        if rng.next() % 3 != 0 || reserved.is_empty() {
+            let size = rng.next() % 0x2_0000 + 1;
+            let alignment = 1 << (rng.next() % 16);
+            if let Some(r) = regions.reserve(size, alignment) {
+                assert_eq!(r.end - r.start, size);
+                assert_eq!(r.start % alignment, 0);
+                reserved.push(r);
+            }
+        } else {
+            let r = reserved.swap_remove((rng.next() % reserved.len() as u64) as usize);
+            assert!(regions.release(r));
+        }
+
+        check(&regions, &reserved, TOTAL);
+    }
+
+    for r in reserved.drain(..) {
+        assert!(regions.release(r));
+    }
+    assert!(regions.regions().eq(iter::once(0..TOTAL)));
+}
+
+fn check(regions: &RegionAllocator, reserved: &[Range<u64>], total: u64) {
+    let mut all: Vec<_> = regions.regions().chain(reserved.iter().cloned()).collect();
+    all.sort_unstable_by_key(|r| r.start);
+    for pair in all.windows(2) {
+        assert!(pair[0].end <= pair[1].start, "{:?} overlaps", pair);
+    }
+
+    let free: Vec<_> = regions.regions().collect();
+    for pair in free.windows(2) {
+        assert!(pair[0].end < pair[1].start, "{:?} not merged", pair);
+    }
+
+    let used: u64 = reserved.iter().map(|r| r.end - r.start).sum();
+    assert_eq!(regions.free_space() + used, total);
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}